@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rhc::keyvalue::KeyValue;
+use rhc::request_definition::RequestDefinition;
+use rhc::templating;
+
+const VARIABLE_COUNT: usize = 1000;
+
+fn variables() -> Vec<KeyValue> {
+    (0..VARIABLE_COUNT)
+        .map(|i| KeyValue::new(&format!("var{}", i), &format!("value-{}", i)))
+        .collect()
+}
+
+// A JSON array of roughly 4 MB, where every element references a few variables
+fn json_body() -> String {
+    let items: Vec<String> = (0..50_000)
+        .map(|i| {
+            format!(
+                r#"{{"id": {}, "name": "{{var{}}}", "tag": "{{var{}|upper}}", "note": "{{missing:none}}", "padding": "lorem ipsum dolor sit amet"}}"#,
+                i,
+                i % VARIABLE_COUNT,
+                (i * 7) % VARIABLE_COUNT
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(",\n"))
+}
+
+// Same size as the JSON body, but with nothing to substitute
+fn plain_body() -> String {
+    "lorem ipsum dolor sit amet, consectetur adipiscing elit\n".repeat(75_000)
+}
+
+fn bench_substitute(c: &mut Criterion) {
+    let vars = variables();
+    let json = json_body();
+    let plain = plain_body();
+
+    let mut group = c.benchmark_group("substitute");
+    group.sample_size(10);
+    group.bench_function("json body with placeholders", |b| {
+        b.iter(|| templating::substitute(black_box(&json), &vars).unwrap())
+    });
+    group.bench_function("body without placeholders", |b| {
+        b.iter(|| templating::substitute(black_box(&plain), &vars).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_substitute_all(c: &mut Criterion) {
+    let vars = variables();
+    let body = json_body();
+    let contents = format!(
+        "[request]\nmethod = \"POST\"\nurl = \"https://example.com/{{var1}}/{{var2}}\"\n\n[body]\ntype = \"json\"\ncontent = '''{}'''\n",
+        body
+    );
+    let def: RequestDefinition = toml::from_str(&contents).unwrap();
+
+    let mut group = c.benchmark_group("substitute_all");
+    group.sample_size(10);
+    group.bench_function("request definition with a json body", |b| {
+        b.iter_batched(
+            || def.clone(),
+            |mut def| templating::substitute_all(&mut def, &vars, &[]).unwrap(),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_substitute, bench_substitute_all);
+criterion_main!(benches);
@@ -8,25 +8,115 @@ pub struct Args {
     #[structopt(parse(from_os_str), help = "The request definition file to use")]
     pub file: Option<PathBuf>,
 
-    #[structopt(short, long, parse(from_os_str), help = "The environment file to use")]
-    pub environment: Option<PathBuf>,
+    #[structopt(
+        short,
+        long,
+        global = true,
+        parse(from_os_str),
+        number_of_values = 1,
+        help = "The environment file to use. Can be given more than once, with later files overriding earlier ones"
+    )]
+    pub environment: Vec<PathBuf>,
 
     #[structopt(long, help = "Only print the response body to stdout")]
     pub only_body: bool,
 
+    #[structopt(
+        long,
+        help = "Also prompt for variables that have a default value or are only used in other variables' values"
+    )]
+    pub prompt_defaults: bool,
+
     #[structopt(
         short,
         long,
+        global = true,
         help = "Bindings to use when constructing the request. Example: -b key=value"
     )]
     pub binding: Option<Vec<KeyValue>>,
 
-    #[structopt(short, long, help = "The config file to use")]
+    #[structopt(
+        long,
+        global = true,
+        parse(from_os_str),
+        help = "A dotenv file to load variables from, overriding the environment file"
+    )]
+    pub dotenv: Option<PathBuf>,
+
+    #[structopt(short, long, global = true, help = "The config file to use")]
     pub config: Option<PathBuf>,
 
-    #[structopt(short, long, help = "Print more detailed information")]
+    #[structopt(short, long, global = true, help = "Print more detailed information")]
     pub verbose: bool,
 
     #[structopt(short, long, help = "File to write output to instead of stdout")]
     pub output_file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    #[structopt(
+        about = "Send request definitions and check the responses against their assertions"
+    )]
+    Test {
+        #[structopt(
+            parse(from_os_str),
+            help = "A request definition file, or a directory to search for request definition files. Defaults to the request definition directory in the config file"
+        )]
+        path: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Write a JUnit XML report to this file"
+        )]
+        junit_report: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str), help = "Write a JSON report to this file")]
+        json_report: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Send the request definitions listed in a workflow file, in order")]
+    Workflow {
+        #[structopt(parse(from_os_str), help = "The workflow file to use")]
+        file: PathBuf,
+    },
+
+    #[structopt(about = "List or clear the cookies saved in the cookie jar")]
+    Cookies {
+        #[structopt(subcommand)]
+        action: CookiesAction,
+    },
+
+    #[structopt(about = "Encrypt a value with the key file, for use in an environment file")]
+    Encrypt {
+        #[structopt(help = "The value to encrypt. Read from stdin if not given")]
+        value: Option<String>,
+    },
+
+    #[structopt(
+        about = "Re-encrypt the encrypted values in an environment file with a new key file"
+    )]
+    Rekey {
+        #[structopt(parse(from_os_str), help = "The environment file to re-encrypt")]
+        file: PathBuf,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "The new key file. The current one is taken from the config file"
+        )]
+        new_key_file: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum CookiesAction {
+    #[structopt(about = "List the saved cookies")]
+    List,
+
+    #[structopt(about = "Remove all the saved cookies")]
+    Clear,
 }
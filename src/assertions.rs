@@ -0,0 +1,217 @@
+use crate::http::CapturedResponse;
+use crate::json_path;
+use crate::request_definition::{Assertions, StatusAssertion};
+use regex::Regex;
+use std::time::Duration;
+
+fn status_matches(assertion: &StatusAssertion, status: u16) -> bool {
+    match assertion {
+        StatusAssertion::Exact(expected) => status == *expected,
+        StatusAssertion::Range { min, max } => *min <= status && status <= *max,
+        StatusAssertion::Class(class) => {
+            // Something like "2xx" or "40x": compare digit by digit, with 'x' matching anything
+            let actual = status.to_string();
+            class.len() == actual.len()
+                && class
+                    .chars()
+                    .zip(actual.chars())
+                    .all(|(c, a)| c.eq_ignore_ascii_case(&'x') || c == a)
+        }
+    }
+}
+
+fn describe_status(assertion: &StatusAssertion) -> String {
+    match assertion {
+        StatusAssertion::Exact(expected) => expected.to_string(),
+        StatusAssertion::Range { min, max } => format!("between {} and {}", min, max),
+        StatusAssertion::Class(class) => class.clone(),
+    }
+}
+
+/// Run all the assertions against a response that took `elapsed` to receive. Returns a description
+/// of each failed assertion, so an empty Vec means everything passed.
+pub fn check(
+    assertions: &Assertions,
+    response: &CapturedResponse,
+    elapsed: Duration,
+) -> Vec<String> {
+    let mut failures: Vec<String> = Vec::new();
+
+    if let Some(status) = &assertions.status {
+        let actual = response.status.as_u16();
+        if !status_matches(status, actual) {
+            failures.push(format!(
+                "Expected status {}, got {}",
+                describe_status(status),
+                actual
+            ));
+        }
+    }
+
+    for header in &assertions.headers {
+        match response
+            .headers
+            .get(header.name.as_str())
+            .map(|v| v.to_str().unwrap_or(""))
+        {
+            None => failures.push(format!(
+                "Expected a {} header, but there was none",
+                header.name
+            )),
+            Some(actual) => {
+                if let Some(expected) = &header.equals {
+                    if actual != expected {
+                        failures.push(format!(
+                            "Expected header {} to equal \"{}\", got \"{}\"",
+                            header.name, expected, actual
+                        ));
+                    }
+                }
+                if let Some(expected) = &header.contains {
+                    if !actual.contains(expected.as_str()) {
+                        failures.push(format!(
+                            "Expected header {} to contain \"{}\", got \"{}\"",
+                            header.name, expected, actual
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    if !assertions.json.is_empty() {
+        match serde_json::from_str::<serde_json::Value>(&response.body) {
+            Err(e) => failures.push(format!("Could not parse the response body as JSON: {}", e)),
+            Ok(body) => {
+                for assertion in &assertions.json {
+                    let found = json_path::lookup(&body, &assertion.path);
+
+                    // With neither `equals` nor `exists`, just check that the path exists
+                    match (found, &assertion.equals, assertion.exists) {
+                        (Some(value), _, Some(false)) => failures.push(format!(
+                            "Expected no value at JSON path {}, got {}",
+                            assertion.path, value
+                        )),
+                        (None, _, Some(false)) => {}
+                        (Some(value), Some(expected), _) if value != expected => {
+                            failures.push(format!(
+                                "Expected JSON path {} to equal {}, got {}",
+                                assertion.path, expected, value
+                            ))
+                        }
+                        (Some(_), _, _) => {}
+                        (None, _, _) => failures
+                            .push(format!("Expected a value at JSON path {}", assertion.path)),
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(pattern) = &assertions.body_matches {
+        match Regex::new(pattern) {
+            Err(e) => failures.push(format!("Invalid regex {}: {}", pattern, e)),
+            Ok(re) => {
+                if !re.is_match(&response.body) {
+                    failures.push(format!("Expected the body to match {}", pattern));
+                }
+            }
+        }
+    }
+
+    if let Some(max) = assertions.max_response_time_ms {
+        let actual = elapsed.as_millis();
+        if actual > u128::from(max) {
+            failures.push(format!(
+                "Expected a response within {} ms, took {} ms",
+                max, actual
+            ));
+        }
+    }
+
+    failures
+}
+
+#[test]
+fn test_status_matches() {
+    assert!(status_matches(&StatusAssertion::Exact(200), 200));
+    assert!(!status_matches(&StatusAssertion::Exact(200), 201));
+    assert!(status_matches(
+        &StatusAssertion::Class("2xx".to_string()),
+        204
+    ));
+    assert!(status_matches(
+        &StatusAssertion::Class("40X".to_string()),
+        404
+    ));
+    assert!(!status_matches(
+        &StatusAssertion::Class("2xx".to_string()),
+        404
+    ));
+    assert!(!status_matches(
+        &StatusAssertion::Class("2xx".to_string()),
+        20
+    ));
+    assert!(status_matches(
+        &StatusAssertion::Range { min: 200, max: 299 },
+        299
+    ));
+    assert!(!status_matches(
+        &StatusAssertion::Range { min: 200, max: 299 },
+        300
+    ));
+}
+
+#[test]
+fn test_check() {
+    let mut headers = attohttpc::header::HeaderMap::new();
+    headers.insert("content-type", "application/json".parse().unwrap());
+    let response = CapturedResponse {
+        status: attohttpc::StatusCode::OK,
+        headers,
+        body: r#"{"id": 5, "name": "bob", "tags": []}"#.to_string(),
+    };
+
+    let passing: Assertions = toml::from_str(
+        r#"
+        status = "2xx"
+        headers = [{ name = "Content-Type", contains = "json" }]
+        json = [
+          { path = "id", equals = 5 },
+          { path = "name", equals = "bob" },
+          { path = "tags" },
+          { path = "missing", exists = false },
+        ]
+        body_matches = "bo+b"
+        max_response_time_ms = 1000
+        "#,
+    )
+    .unwrap();
+    let no_failures: Vec<String> = vec![];
+    assert_eq!(
+        check(&passing, &response, Duration::from_millis(10)),
+        no_failures
+    );
+
+    let failing: Assertions = toml::from_str(
+        r#"
+        status = { min = 400, max = 499 }
+        headers = [{ name = "x-missing", equals = "a" }]
+        json = [{ path = "name", equals = "alice" }, { path = "id", exists = false }]
+        body_matches = "alice"
+        max_response_time_ms = 5
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        check(&failing, &response, Duration::from_millis(10)),
+        vec![
+            "Expected status between 400 and 499, got 200",
+            "Expected a x-missing header, but there was none",
+            "Expected JSON path name to equal \"alice\", got \"bob\"",
+            "Expected no value at JSON path id, got 5",
+            "Expected the body to match alice",
+            "Expected a response within 5 ms, took 10 ms",
+        ]
+    );
+}
@@ -0,0 +1,519 @@
+use crate::request_definition::{
+    AwsSigV4, CanonicalPart, HmacAlgorithm, HmacSignature, SignatureEncoding, TimestampFormat,
+};
+use anyhow::anyhow;
+use attohttpc::header::HeaderMap;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{BTreeMap, HashMap};
+use url::{Position, Url};
+
+/// Lowercase hex encoding, as used in digests and signatures
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Parse the parameters of a challenge like `Digest realm="a", nonce="b", qop="auth,auth-int"`.
+// Quoted values can contain commas, and the parameter names are case-insensitive.
+fn parse_challenge(challenge: &str) -> anyhow::Result<HashMap<String, String>> {
+    let trimmed = challenge.trim();
+    if trimmed.len() < 7 || !trimmed[..7].eq_ignore_ascii_case("digest ") {
+        return Err(anyhow!("Expected a Digest challenge, got {}", challenge));
+    }
+
+    let mut params: HashMap<String, String> = HashMap::new();
+    let mut rest = trimmed[7..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| anyhow!("Malformed Digest challenge: {}", challenge))?;
+        let name = rest[..eq].trim().to_lowercase();
+        rest = rest[eq + 1..].trim_start();
+
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted
+                .find('"')
+                .ok_or_else(|| anyhow!("Malformed Digest challenge: {}", challenge))?;
+            let value = &quoted[..end];
+            rest = &quoted[end + 1..];
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..end].trim();
+            rest = &rest[end..];
+            value
+        };
+        params.insert(name, value.to_string());
+
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    Ok(params)
+}
+
+// A nonce chosen by the client, which doesn't need to be cryptographically random
+fn client_nonce() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    hex(&Md5::digest(
+        format!("{}{}", nanos, std::process::id()).as_bytes(),
+    ))
+}
+
+/// Build the value of an Authorization header that answers the Digest challenge from a
+/// WWW-Authenticate header. `uri` is the path and query string of the request.
+pub fn digest_authorization(
+    challenge: &str,
+    username: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+) -> anyhow::Result<String> {
+    digest_authorization_with_cnonce(challenge, username, password, method, uri, &client_nonce())
+}
+
+fn digest_authorization_with_cnonce(
+    challenge: &str,
+    username: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+    cnonce: &str,
+) -> anyhow::Result<String> {
+    let params = parse_challenge(challenge)?;
+    let realm = params.get("realm").map(String::as_str).unwrap_or("");
+    let nonce = params
+        .get("nonce")
+        .ok_or_else(|| anyhow!("The Digest challenge has no nonce"))?;
+    let algorithm = params.get("algorithm").map(String::as_str).unwrap_or("MD5");
+
+    let hash: fn(&str) -> String = match algorithm.to_uppercase().trim_end_matches("-SESS") {
+        "MD5" => |s| hex(&Md5::digest(s.as_bytes())),
+        "SHA-256" => |s| hex(&Sha256::digest(s.as_bytes())),
+        _ => return Err(anyhow!("Unsupported Digest algorithm {}", algorithm)),
+    };
+
+    let mut ha1 = hash(&format!("{}:{}:{}", username, realm, password));
+    if algorithm.to_uppercase().ends_with("-SESS") {
+        ha1 = hash(&format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = hash(&format!("{}:{}", method, uri));
+
+    // Only the "auth" quality of protection is supported, not "auth-int"
+    let qop_auth = params
+        .get("qop")
+        .is_some_and(|qop| qop.split(',').any(|q| q.trim() == "auth"));
+    let nc = "00000001";
+
+    let response = if qop_auth {
+        hash(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2))
+    } else {
+        hash(&format!("{}:{}:{}", ha1, nonce, ha2))
+    };
+
+    let mut header = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+        username, realm, nonce, uri, algorithm, response
+    );
+    if qop_auth {
+        header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+    }
+    if let Some(opaque) = params.get("opaque") {
+        header.push_str(&format!(", opaque=\"{}\"", opaque));
+    }
+
+    Ok(header)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this can't fail
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything except the unreserved characters from RFC 3986, which is also the way
+/// AWS expects
+pub fn uri_encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3).and_then(|hex| {
+            std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        });
+        match (bytes[i], escaped) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// The headers to add to a request in order to sign it with AWS Signature Version 4, including
+/// Authorization. `headers` are the headers that the request already has, which are all signed.
+pub fn aws_sigv4_headers(
+    settings: &AwsSigV4,
+    method: &str,
+    url: &Url,
+    headers: &HeaderMap,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Vec<(&'static str, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex(&Sha256::digest(body));
+
+    let mut added: Vec<(&'static str, String)> = vec![("x-amz-date", amz_date.clone())];
+    // S3 requires the payload hash as a header; other services don't
+    if settings.service == "s3" {
+        added.push(("x-amz-content-sha256", payload_hash.clone()));
+    }
+    if let Some(session_token) = &settings.session_token {
+        added.push(("x-amz-security-token", session_token.clone()));
+    }
+
+    // Every path segment is URI-encoded twice, except for S3
+    let canonical_path = url
+        .path()
+        .split('/')
+        .map(|segment| {
+            let encoded = uri_encode(&percent_decode(segment));
+            if settings.service == "s3" {
+                encoded
+            } else {
+                uri_encode(encoded.as_bytes())
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("/");
+    let canonical_path = if canonical_path.is_empty() {
+        "/".to_string()
+    } else {
+        canonical_path
+    };
+
+    let mut query: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| (uri_encode(name.as_bytes()), uri_encode(value.as_bytes())))
+        .collect();
+    query.sort();
+    let canonical_query = query
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join("&");
+
+    // Header name -> values, with whitespace trimmed and collapsed
+    let mut canonical_headers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+        None => url.host_str().unwrap_or("").to_string(),
+    };
+    canonical_headers.insert("host".to_string(), vec![host]);
+    let existing = headers
+        .iter()
+        .filter(|(name, _)| {
+            *name != attohttpc::header::AUTHORIZATION && *name != attohttpc::header::HOST
+        })
+        .map(|(name, value)| (name.as_str(), String::from_utf8_lossy(value.as_bytes())));
+    let added_headers = added
+        .iter()
+        .map(|(name, value)| (*name, std::borrow::Cow::Borrowed(value.as_str())));
+    for (name, value) in existing.chain(added_headers) {
+        canonical_headers
+            .entry(name.to_lowercase())
+            .or_default()
+            .push(value.split_whitespace().collect::<Vec<&str>>().join(" "));
+    }
+
+    let signed_headers = canonical_headers
+        .keys()
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .join(";");
+    let canonical_headers: String = canonical_headers
+        .iter()
+        .map(|(name, values)| format!("{}:{}\n", name, values.join(",")))
+        .collect();
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, canonical_path, canonical_query, canonical_headers, signed_headers, payload_hash
+    );
+
+    let scope = format!(
+        "{}/{}/{}/aws4_request",
+        date, settings.region, settings.service
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let signing_key = [
+        settings.region.as_bytes(),
+        settings.service.as_bytes(),
+        b"aws4_request",
+    ]
+    .iter()
+    .fold(
+        hmac_sha256(
+            format!("AWS4{}", settings.secret_key).as_bytes(),
+            date.as_bytes(),
+        ),
+        |key, data| hmac_sha256(&key, data),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    added.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            settings.access_key, scope, signed_headers, signature
+        ),
+    ));
+    added
+}
+
+/// The headers to add to a request in order to sign it with an HMAC: the signature, and the
+/// timestamp if it's configured to be sent.
+pub fn hmac_headers(
+    settings: &HmacSignature,
+    method: &str,
+    url: &Url,
+    headers: &HeaderMap,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let timestamp = match settings.timestamp_format {
+        TimestampFormat::Unix => now.timestamp().to_string(),
+        TimestampFormat::UnixMs => now.timestamp_millis().to_string(),
+        TimestampFormat::Iso8601 => now.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+    };
+
+    let mut parts: Vec<String> = Vec::new();
+    for part in &settings.canonical {
+        let value = match part {
+            CanonicalPart::Method => method.to_string(),
+            CanonicalPart::Url => url.to_string(),
+            CanonicalPart::Host => url[Position::BeforeHost..Position::AfterPort].to_string(),
+            CanonicalPart::Path => url.path().to_string(),
+            CanonicalPart::Query => url.query().unwrap_or("").to_string(),
+            CanonicalPart::PathAndQuery => url[Position::BeforePath..].to_string(),
+            CanonicalPart::Timestamp => timestamp.clone(),
+            CanonicalPart::Body => String::from_utf8_lossy(body).into_owned(),
+            CanonicalPart::BodySha256 => hex(&Sha256::digest(body)),
+            CanonicalPart::Header(name) => headers
+                .get(name.as_str())
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                .ok_or_else(|| anyhow!("The request has no {} header to sign", name))?,
+        };
+        parts.push(value);
+    }
+    let canonical_string = parts.join(&settings.separator);
+
+    let key = settings.key.as_bytes();
+    let signature: Vec<u8> = match settings.algorithm {
+        HmacAlgorithm::Sha256 => hmac_sha256(key, canonical_string.as_bytes()),
+        HmacAlgorithm::Sha512 => {
+            let mut mac = Hmac::<Sha512>::new_from_slice(key).unwrap();
+            mac.update(canonical_string.as_bytes());
+            mac.finalize().into_bytes().to_vec()
+        }
+    };
+    let signature = match settings.encoding {
+        SignatureEncoding::Hex => hex(&signature),
+        SignatureEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(&signature),
+    };
+
+    let mut result: Vec<(String, String)> = Vec::new();
+    if let Some(timestamp_header) = &settings.timestamp_header {
+        result.push((timestamp_header.clone(), timestamp));
+    }
+    result.push((settings.header.clone(), signature));
+
+    Ok(result)
+}
+
+#[test]
+fn test_parse_challenge() {
+    let params =
+        parse_challenge(r#"Digest Realm="a, b", nonce="xyz",qop="auth,auth-int" , stale=FALSE"#)
+            .unwrap();
+    assert_eq!(params["realm"], "a, b");
+    assert_eq!(params["nonce"], "xyz");
+    assert_eq!(params["qop"], "auth,auth-int");
+    assert_eq!(params["stale"], "FALSE");
+
+    assert!(parse_challenge(r#"Basic realm="a""#).is_err());
+}
+
+#[test]
+fn test_digest_authorization() {
+    // The example from RFC 2617
+    let challenge = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+    let header = digest_authorization_with_cnonce(
+        challenge,
+        "Mufasa",
+        "Circle Of Life",
+        "GET",
+        "/dir/index.html",
+        "0a4f113b",
+    )
+    .unwrap();
+
+    assert_eq!(
+        header,
+        r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", algorithm=MD5, response="6629fae49393a05397450978507c4ef1", qop=auth, nc=00000001, cnonce="0a4f113b", opaque="5ccc069c403ebaf9f0171e9517f40e41""#
+    );
+
+    assert!(digest_authorization(
+        r#"Digest realm="a", nonce="b", algorithm=SHA-512-256"#,
+        "user",
+        "pass",
+        "GET",
+        "/"
+    )
+    .is_err());
+}
+
+#[test]
+fn test_aws_sigv4_headers() {
+    use chrono::TimeZone;
+
+    // Examples from the AWS Signature Version 4 test suite
+    let settings = AwsSigV4 {
+        region: "us-east-1".to_string(),
+        service: "service".to_string(),
+        access_key: "AKIDEXAMPLE".to_string(),
+        secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        session_token: None,
+    };
+    let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+
+    let headers = aws_sigv4_headers(
+        &settings,
+        "GET",
+        &Url::parse("https://example.amazonaws.com/").unwrap(),
+        &HeaderMap::new(),
+        b"",
+        now,
+    );
+    assert_eq!(
+        headers,
+        vec![
+            ("x-amz-date", "20150830T123600Z".to_string()),
+            ("authorization", "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31".to_string()),
+        ]
+    );
+
+    let headers = aws_sigv4_headers(
+        &settings,
+        "GET",
+        &Url::parse("https://example.amazonaws.com/?Param2=value2&Param1=value1").unwrap(),
+        &HeaderMap::new(),
+        b"",
+        now,
+    );
+    assert!(headers[1]
+        .1
+        .ends_with("Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"));
+}
+
+#[test]
+fn test_hmac_headers() {
+    use chrono::TimeZone;
+
+    let mut settings: HmacSignature = toml::from_str(
+        r#"
+        key = "secret"
+        canonical = ["method", "path_and_query", "timestamp", "header:X-Client", "body_sha256"]
+        header = "X-Signature"
+        timestamp_header = "X-Timestamp"
+        "#,
+    )
+    .unwrap();
+    let url = Url::parse("https://api.example.com/orders?id=5").unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-client", "rhc".parse().unwrap());
+    let now = Utc.with_ymd_and_hms(2020, 5, 1, 12, 30, 0).unwrap();
+
+    // The signature of "POST\n/orders?id=5\n1588336200\nrhc\n<sha256 of the body>"
+    let canonical = format!(
+        "POST\n/orders?id=5\n1588336200\nrhc\n{}",
+        hex(&Sha256::digest(b"{}"))
+    );
+    assert_eq!(
+        hmac_headers(&settings, "POST", &url, &headers, b"{}", now).unwrap(),
+        vec![
+            ("X-Timestamp".to_string(), "1588336200".to_string()),
+            (
+                "X-Signature".to_string(),
+                hex(&hmac_sha256(b"secret", canonical.as_bytes()))
+            ),
+        ]
+    );
+
+    settings.canonical = vec![CanonicalPart::Method, CanonicalPart::Timestamp];
+    settings.separator = "|".to_string();
+    settings.encoding = SignatureEncoding::Base64;
+    settings.timestamp_format = TimestampFormat::Iso8601;
+    settings.timestamp_header = None;
+    assert_eq!(
+        hmac_headers(&settings, "GET", &url, &headers, b"", now).unwrap(),
+        vec![(
+            "X-Signature".to_string(),
+            base64::engine::general_purpose::STANDARD
+                .encode(hmac_sha256(b"secret", b"GET|2020-05-01T12:30:00Z"))
+        )]
+    );
+
+    // A known HMAC-SHA256 test vector
+    settings.key = "key".to_string();
+    settings.canonical = vec![CanonicalPart::Body];
+    settings.encoding = SignatureEncoding::Hex;
+    assert_eq!(
+        hmac_headers(
+            &settings,
+            "POST",
+            &url,
+            &headers,
+            b"The quick brown fox jumps over the lazy dog",
+            now
+        )
+        .unwrap()[0]
+            .1,
+        "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+    );
+
+    settings.canonical = vec![CanonicalPart::Header("X-Missing".to_string())];
+    assert!(hmac_headers(&settings, "GET", &url, &headers, b"", now).is_err());
+}
@@ -1,21 +1,34 @@
 use anyhow::{anyhow, Context};
 use atty::Stream;
-use rhc::args::Args;
+use chrono::{TimeZone, Utc};
+use rhc::args::{Args, Command, CookiesAction};
 use rhc::config::Config;
+use rhc::cookies::CookieJar;
+use rhc::dotenv;
+use rhc::encryption::{self, Key};
 use rhc::environment::Environment;
+use rhc::extract;
 use rhc::files::{get_all_toml_files, load_file};
 use rhc::http;
+use rhc::http::{CapturedResponse, RequestContext};
 use rhc::interactive;
-use rhc::interactive::SelectedValues;
+use rhc::interactive::{SelectedValues, VariableInfo};
+use rhc::keyvalue;
 use rhc::keyvalue::KeyValue;
+use rhc::report;
 use rhc::request_definition::RequestDefinition;
+use rhc::runner;
+use rhc::runner::TestResult;
+use rhc::secrets::Secrets;
+use rhc::session::Session;
 use rhc::templating;
+use rhc::workflow::Workflow;
 use serde_json::{to_string_pretty, Value};
 use spinners::{Spinner, Spinners};
 use std::borrow::Cow;
 use std::env;
 use std::fs::OpenOptions;
-use std::io::{Stdout, Write};
+use std::io::{Read, Stdout, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -58,6 +71,135 @@ fn get_terminal() -> anyhow::Result<OurTerminal> {
     Ok(t)
 }
 
+/// Load the dotenv file passed with --dotenv, or else the one in the config file if it exists.
+fn dotenv_variables(path: Option<&Path>, config: &Config) -> anyhow::Result<Vec<KeyValue>> {
+    match (path, &config.dotenv_file) {
+        (Some(path), _) => dotenv::load(path),
+        (None, Some(dotenv_file)) => {
+            let path = PathBuf::from(shellexpand::tilde(dotenv_file).as_ref());
+            if path.is_file() {
+                dotenv::load(&path)
+            } else {
+                Ok(vec![])
+            }
+        }
+        (None, None) => Ok(vec![]),
+    }
+}
+
+/// Load the environment files given with `-e`, each layered over the ones before it
+fn load_environments(paths: &[PathBuf], config: &Config) -> anyhow::Result<Option<Environment>> {
+    let mut result: Option<Environment> = None;
+    for path in paths {
+        let env = load_file(path, |path| Environment::new(path, config), "environment")?;
+        result = Some(match result {
+            Some(base) => env.over(base),
+            None => env,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Gather all the variables that are bound before any interactive prompting, in increasing order
+/// of priority: the environment file, the dotenv file, values extracted from earlier responses,
+/// and bindings passed on the command line. Also returns the environment's name.
+fn bound_variables(
+    env: Option<Environment>,
+    dotenv: Vec<KeyValue>,
+    session: &Session,
+    bindings: Option<Vec<KeyValue>>,
+) -> anyhow::Result<(Vec<KeyValue>, String)> {
+    // Split up the variables and environment name immediately to avoid difficulties with borrowing
+    // `env` later on
+    let (mut vars, env_name): (Vec<KeyValue>, String) =
+        env.map_or((vec![], "<none>".to_string()), |e| (e.variables, e.name));
+
+    vars.sort();
+    keyvalue::merge(&mut vars, dotenv);
+
+    let extracted = session
+        .variables(&env_name)
+        .into_iter()
+        .map(|var| KeyValue::literal(&var.name, &var.value))
+        .collect();
+    keyvalue::merge(&mut vars, extracted);
+
+    if let Some(bindings) = bindings {
+        keyvalue::merge(&mut vars, bindings);
+    }
+
+    Ok((vars, env_name))
+}
+
+fn write_test_result<W: Write>(writer: &mut W, result: &TestResult) -> std::io::Result<()> {
+    let label = if result.passed() { "PASS" } else { "FAIL" };
+    writeln!(
+        writer,
+        "{}  {} ({} ms)",
+        label,
+        result.name,
+        result.elapsed.as_millis()
+    )?;
+    for failure in result.error.iter().chain(result.failures.iter()) {
+        writeln!(writer, "        {}", failure)?;
+    }
+
+    Ok(())
+}
+
+/// Send every request definition under `path` (or the request definition directory) and print
+/// which ones passed their assertions.
+fn run_tests<W: Write>(
+    path: Option<PathBuf>,
+    vars: &[KeyValue],
+    context: &mut RequestContext,
+    writer: &mut W,
+) -> anyhow::Result<Vec<TestResult>> {
+    let base = path.unwrap_or_else(|| {
+        PathBuf::from(shellexpand::tilde(&context.config.request_definition_directory).as_ref())
+    });
+
+    let mut paths: Vec<PathBuf> = if base.is_dir() {
+        get_all_toml_files(&base.to_string_lossy())
+            .into_iter()
+            .filter(|path| !Workflow::is_workflow(path))
+            .collect()
+    } else {
+        vec![base.clone()]
+    };
+    paths.sort();
+
+    if paths.is_empty() {
+        return Err(anyhow!("No TOML files found under {}", base.display()));
+    }
+
+    let mut results: Vec<TestResult> = Vec::new();
+    for path in &paths {
+        let name = path
+            .strip_prefix(&base)
+            .ok()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(path)
+            .to_string_lossy();
+
+        let result = runner::run_test(path, &name, vars, context);
+        write_test_result(writer, &result)?;
+        results.push(result);
+    }
+
+    let passed = results.iter().filter(|r| r.passed()).count();
+    writeln!(
+        writer,
+        "\n{} passed, {} failed",
+        passed,
+        results.len() - passed
+    )?;
+    writer.flush()?;
+
+    Ok(results)
+}
+
 fn run() -> anyhow::Result<()> {
     // CombinedLogger::init(
     //     vec![
@@ -134,6 +276,164 @@ fn run() -> anyhow::Result<()> {
         }
     };
 
+    // Loaded up front so that `{$env.NAME}` can see its variables in every mode
+    let dotenv = dotenv_variables(args.dotenv.as_deref(), &config)?;
+
+    if let Some(Command::Workflow { file }) = args.command {
+        let workflow = load_file(&file, Workflow::new, "workflow")?;
+        let env = load_environments(&args.environment, &config)?;
+        let default_auth = env.as_ref().and_then(|e| e.auth.clone());
+        let commands = env.as_ref().map_or(vec![], |e| e.commands.clone());
+        let mut session = Session::load(&config)?;
+        let (vars, env_name) = bound_variables(env, dotenv.clone(), &session, args.binding)?;
+        let mut context = RequestContext::new(&config, &env_name, default_auth, commands, dotenv)?;
+
+        if let Some(name) = &workflow.name {
+            writeln!(writer, "{}\n", name)?;
+        }
+
+        let result = workflow.run(vars, &mut context, |step| {
+            write_test_result(&mut writer, step)?;
+            writer.flush()?;
+            Ok(())
+        })?;
+
+        // Like a single request, extracted values are saved for later runs
+        if !result.extracted.is_empty() {
+            session.set(&env_name, &result.extracted);
+            session.save()?;
+        }
+        context.save()?;
+
+        let failed = result.steps.iter().filter(|r| !r.passed()).count();
+        let skipped = workflow.steps.len() - result.steps.len();
+        writeln!(
+            writer,
+            "\n{} passed, {} failed, {} skipped",
+            result.steps.len() - failed,
+            failed,
+            skipped
+        )?;
+        writer.flush()?;
+
+        return if failed > 0 {
+            Err(anyhow!(
+                "{} of {} steps failed",
+                failed,
+                workflow.steps.len()
+            ))
+        } else {
+            Ok(())
+        };
+    }
+
+    if let Some(Command::Cookies { action }) = args.command {
+        let env_name =
+            load_environments(&args.environment, &config)?.map_or("<none>".to_string(), |e| e.name);
+
+        let mut jar = CookieJar::load(&config, &env_name)?
+            .ok_or_else(|| anyhow!("No cookie_jar is set in the config file"))?;
+
+        match action {
+            CookiesAction::List => {
+                for cookie in jar.cookies() {
+                    let expires = cookie
+                        .expires
+                        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+                        .map_or("session".to_string(), |expires| expires.to_rfc2822());
+                    writeln!(
+                        writer,
+                        "{}={}\t{}{}\t{}\t{}",
+                        cookie.name,
+                        cookie.value,
+                        if cookie.host_only { "" } else { "." },
+                        cookie.domain,
+                        cookie.path,
+                        expires
+                    )?;
+                }
+            }
+            CookiesAction::Clear => {
+                jar.clear();
+                jar.save()?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Command::Encrypt { value }) = args.command {
+        // Reading the value from stdin keeps it out of the shell history
+        let value = match value {
+            Some(value) => value,
+            None => {
+                let mut value = String::new();
+                std::io::stdin().read_to_string(&mut value)?;
+                value.trim_end_matches(&['\r', '\n'][..]).to_string()
+            }
+        };
+
+        let encrypted = Key::load(&config)?.encrypt(&value)?;
+        writeln!(writer, "{}", encrypted)?;
+        return Ok(());
+    }
+
+    if let Some(Command::Rekey { file, new_key_file }) = args.command {
+        let contents = std::fs::read_to_string(&file)
+            .with_context(|| format!("Could not read {}", file.display()))?;
+        let (rekeyed, count) = encryption::rekey(
+            &contents,
+            &mut Key::load(&config)?,
+            &mut Key::from_file(&new_key_file)?,
+        )
+        .with_context(|| format!("Could not re-encrypt {}", file.display()))?;
+        std::fs::write(&file, rekeyed)
+            .with_context(|| format!("Could not write {}", file.display()))?;
+
+        writeln!(
+            writer,
+            "Re-encrypted {} values in {}",
+            count,
+            file.display()
+        )?;
+        return Ok(());
+    }
+
+    if let Some(Command::Test {
+        path,
+        junit_report,
+        json_report,
+    }) = args.command
+    {
+        let env = load_environments(&args.environment, &config)?;
+        let default_auth = env.as_ref().and_then(|e| e.auth.clone());
+        let commands = env.as_ref().map_or(vec![], |e| e.commands.clone());
+        let session = Session::load(&config)?;
+        let (vars, env_name) = bound_variables(env, dotenv.clone(), &session, args.binding)?;
+        let mut context = RequestContext::new(&config, &env_name, default_auth, commands, dotenv)?;
+
+        let results = run_tests(path, &vars, &mut context, &mut writer)?;
+        context.save()?;
+
+        if let Some(junit_report) = junit_report {
+            std::fs::write(&junit_report, report::junit(&results)).with_context(|| {
+                format!("Failed writing JUnit report to {}", junit_report.display())
+            })?;
+        }
+        if let Some(json_report) = json_report {
+            std::fs::write(&json_report, to_string_pretty(&report::json(&results))?).with_context(
+                || format!("Failed writing JSON report to {}", json_report.display()),
+            )?;
+        }
+
+        let failed = results.iter().filter(|r| !r.passed()).count();
+        return if failed > 0 {
+            Err(anyhow!("{} of {} tests failed", failed, results.len()))
+        } else {
+            Ok(())
+        };
+    }
+
     let is_tty = atty::is(Stream::Stdout);
 
     // These two are necessary for use in interactive mode; but conversely, when not at an
@@ -154,11 +454,7 @@ fn run() -> anyhow::Result<()> {
             Some(path) => {
                 let def: RequestDefinition =
                     load_file(&path, RequestDefinition::new, "request definition")?;
-                let env_path: Option<PathBuf> = args.environment;
-                let env: Option<Environment> = env_path
-                    .as_deref()
-                    .map(|path| load_file(&path, Environment::new, "environment"))
-                    .transpose()?;
+                let env = load_environments(&args.environment, &config)?;
 
                 Ok(Some(SelectedValues { def, env }))
             }
@@ -170,17 +466,32 @@ fn run() -> anyhow::Result<()> {
                     if get_all_toml_files(&config.request_definition_directory).is_empty() {
                         Err(anyhow!("No TOML files found under {}. Running rhc in interactive mode requres at least one request definition file.", &config.request_definition_directory))
                     } else {
+                        // Only one environment can be selected here. The last one given with -e is
+                        // selected at first, and the ones before it are layered under whichever
+                        // one ends up selected.
+                        let (selected, layers) = match args.environment.split_last() {
+                            Some((last, layers)) => (Some(last.as_path()), layers),
+                            None => (None, &[][..]),
+                        };
+                        let base = load_environments(layers, &config)?;
+
                         // `terminal` and `keys` must be None at this point, so just create them
                         terminal = Some(get_terminal()?);
                         keys = Some(termion::async_stdin().keys());
                         let interactive_result = interactive::interactive_mode(
                             &config,
-                            args.environment.as_deref(),
+                            selected,
                             &mut keys.as_mut().unwrap(),
                             &mut terminal.as_mut().unwrap(),
                         )?;
 
-                        Ok(interactive_result)
+                        Ok(interactive_result.map(|SelectedValues { def, env }| {
+                            let env = match (env, base) {
+                                (Some(env), Some(base)) => Some(env.over(base)),
+                                (env, base) => env.or(base),
+                            };
+                            SelectedValues { def, env }
+                        }))
                     }
                 } else {
                     Err(anyhow!("Running in interactive mode requires a TTY"))
@@ -194,32 +505,33 @@ fn run() -> anyhow::Result<()> {
     // `interactive_mode` will return None if they Ctrl-C out without selecting anything.
     // if let Some((mut request_definition, mut vars)) = result {
     if let Some(SelectedValues { mut def, env }) = result {
-        // Split up the variables and environment name immediately to avoid difficulties with borrowing
-        // `env` later on
-        let (mut vars, env_name): (Vec<KeyValue>, String) =
-            env.map_or((vec![], "<none>".to_string()), |e| (e.variables, e.name));
-
-        vars.sort();
-        if let Some(bindings) = args.binding {
-            for binding in bindings {
-                match vars.binary_search_by(|item| item.name.cmp(&binding.name)) {
-                    Ok(index) => {
-                        // If variable is already present, overwrite it with the one passed on the
-                        // command line (these have the highest priority)
-                        vars.remove(index);
-                        vars.insert(index, binding);
-                    }
-                    Err(index) => vars.insert(index, binding),
-                };
-            }
-        }
+        // If the body comes from a file (or stdin), read it now so that its variables can be bound
+        def.load_body_file()?;
 
-        // Substitute the variables that we have at this point into all the places of the
-        // RequestDefinitions that they can be used (URL, headers, body, query string)
-        templating::substitute_all(&mut def, &vars);
+        // The environment's auth table applies if the definition doesn't have one
+        if def.auth.is_none() {
+            def.auth = env.as_ref().and_then(|e| e.auth.clone());
+        }
 
-        // // If any unbound variables remain, prompt the user to enter them interactively
-        let unbound_variables = templating::list_unbound_variables(&def);
+        let commands = env.as_ref().map_or(vec![], |e| e.commands.clone());
+        let env_secrets = env.as_ref().map_or(vec![], |e| e.secrets.clone());
+        let secrets = Secrets::new(&config, &env_secrets, &def)?;
+        let mut session = Session::load(&config)?;
+        let (mut vars, env_name) = bound_variables(env, dotenv.clone(), &session, args.binding)?;
+        let mut context = RequestContext::new(&config, &env_name, None, commands, dotenv)?;
+
+        // Run the commands for any command variables that are used and not bound otherwise
+        let command_vars = context
+            .commands
+            .resolve(&templating::list_unbound_variables(&def, &vars, true))?;
+        keyvalue::merge(&mut vars, command_vars);
+
+        // If any variables aren't bound by what we have at this point, prompt the user to enter
+        // them interactively. Ones with a default value are only prompted for if the user asked
+        // for it.
+        let unbound_variables =
+            templating::list_unbound_variables(&def, &vars, args.prompt_defaults);
+        let defaults = templating::list_defaults(&def, &vars);
 
         let additional_vars: anyhow::Result<Option<Vec<KeyValue>>> = {
             if !unbound_variables.is_empty() {
@@ -233,6 +545,11 @@ fn run() -> anyhow::Result<()> {
                     interactive::prompt_for_variables(
                         &config,
                         unbound_variables,
+                        &VariableInfo {
+                            defaults: &defaults,
+                            declarations: &def.variables,
+                            secrets: &secrets,
+                        },
                         &env_name,
                         &mut keys.as_mut().unwrap(),
                         &mut terminal.as_mut().unwrap(),
@@ -256,24 +573,32 @@ fn run() -> anyhow::Result<()> {
         // `prompt_for_variables` returning None means the user aborted with Ctrl-C and we
         // should not send the request
         if let Some(additional_vars) = additional_vars {
-            // Do the final substition with the user-provided variables
-            templating::substitute_all(&mut def, &additional_vars);
+            // Substitute all the variables into all the places of the RequestDefinition that they
+            // can be used (URL, headers, body, query string) in one go, so that values containing
+            // braces aren't mistaken for variables
+            keyvalue::merge(&mut vars, additional_vars);
+            templating::substitute_all(&mut def, &vars, &context.dotenv)?;
 
             let mut sp: Option<Spinner> = None;
             if is_tty {
                 sp = Some(Spinner::new(Spinners::Dots, "Sending request...".into()));
             }
 
-            let res = http::send_request(def, &config).context("Failed sending request")?;
+            // The definition is consumed when sending, so hold on to what needs to be extracted
+            let extractions = def.extract.take();
+
+            let res = http::send_request(def, &mut context).context("Failed sending request")?;
+            let res = CapturedResponse::new(res).context("Failed reading response")?;
+            context.save()?;
             if let Some(s) = sp {
                 s.stop();
                 writeln!(writer, "\n")?;
             }
 
-            let headers = res.headers();
+            let headers = &res.headers;
 
             if !(&args.only_body) {
-                writeln!(writer, "{}\n", res.status())?;
+                writeln!(writer, "{}\n", res.status)?;
                 for (name, value) in headers {
                     let value = value.to_str()?;
                     writeln!(writer, "{}: {}", name.as_str(), value)?;
@@ -298,7 +623,7 @@ fn run() -> anyhow::Result<()> {
                 // try to parse it as a generic Value, then pretty-print it with highlighting via
                 // syntect. If the parsing fails, give up on the pretty-printing and just print the
                 // raw text response (still with JSON highlighting, if possible)
-                let body: Value = res.json()?;
+                let body: Value = serde_json::from_str(&res.body)?;
                 let body = to_string_pretty(&body).unwrap_or_else(|_| body.to_string());
 
                 let ps = SyntaxSet::load_defaults_newlines();
@@ -343,8 +668,26 @@ fn run() -> anyhow::Result<()> {
                     }
                 }
             } else {
-                let body = res.text()?;
-                writeln!(writer, "{}", body)?;
+                writeln!(writer, "{}", res.body)?;
+            }
+
+            if let Some(extractions) = extractions {
+                let extracted = extract::extract_values(&extractions.values, &res)
+                    .context("Failed extracting values from the response")?;
+
+                if args.verbose {
+                    for var in &extracted {
+                        writeln!(
+                            writer,
+                            "Extracted {} = {}",
+                            var.name,
+                            secrets.redact(&var.name, &var.value)
+                        )?;
+                    }
+                }
+
+                session.set(&env_name, &extracted);
+                session.save()?;
             }
         }
     }
@@ -53,7 +53,8 @@ impl Choice {
             Some(Ok(request_definition)) => {
                 let initial_url = &request_definition.request.url;
                 variables
-                    .map(|vars| substitute(initial_url, vars).0)
+                    .and_then(|vars| substitute(initial_url, vars).ok())
+                    .map(|(url, _)| url)
                     .unwrap_or(Cow::Borrowed(initial_url))
             }
             _ => "".into(),
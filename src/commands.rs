@@ -0,0 +1,109 @@
+use crate::keyvalue::KeyValue;
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::process::Command;
+
+/// A variable whose value is the output of a shell command, like
+/// `{ name = "token", command = "gcloud auth print-access-token" }`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandVariable {
+    pub name: String,
+    pub command: String,
+}
+
+/// Runs the commands for command variables on demand, so that a command only runs if its variable
+/// is actually used, and at most once per run of rhc.
+pub struct CommandVariables {
+    commands: Vec<CommandVariable>,
+    values: HashMap<String, String>,
+}
+
+impl CommandVariables {
+    pub fn new(commands: Vec<CommandVariable>) -> CommandVariables {
+        CommandVariables {
+            commands,
+            values: HashMap::new(),
+        }
+    }
+
+    /// Bind whichever of `names` have a command, sorted by name
+    pub fn resolve(&mut self, names: &[&str]) -> anyhow::Result<Vec<KeyValue>> {
+        let mut result: Vec<KeyValue> = vec![];
+        for command in &self.commands {
+            if !names.contains(&command.name.as_str()) {
+                continue;
+            }
+
+            let value = match self.values.get(&command.name) {
+                Some(value) => value.clone(),
+                None => {
+                    let value = run(&command.command).with_context(|| {
+                        format!("The command for the variable {} failed", command.name)
+                    })?;
+                    self.values.insert(command.name.clone(), value.clone());
+                    value
+                }
+            };
+
+            result.push(KeyValue::literal(&command.name, &value));
+        }
+
+        result.sort();
+        Ok(result)
+    }
+}
+
+// The command's stdout, minus the trailing newline that nearly every command prints
+fn run(command: &str) -> anyhow::Result<String> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .output()
+        .with_context(|| format!("Could not run `{}`", command))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`{}` exited with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .with_context(|| format!("The output of `{}` is not valid UTF-8", command))?;
+    Ok(stdout.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+#[test]
+fn test_command_variables() {
+    let dir = tempfile::tempdir().unwrap();
+    let counter = dir.path().join("counter");
+    let mut commands = CommandVariables::new(vec![
+        CommandVariable {
+            name: "token".to_string(),
+            command: format!("echo run >> {}; printf 'abc\\n'", counter.display()),
+        },
+        CommandVariable {
+            name: "unused".to_string(),
+            command: "exit 1".to_string(),
+        },
+        CommandVariable {
+            name: "failing".to_string(),
+            command: "echo oops >&2; exit 3".to_string(),
+        },
+    ]);
+
+    assert_eq!(
+        commands.resolve(&["token", "other"]).unwrap(),
+        vec![KeyValue::new("token", "abc")]
+    );
+    assert_eq!(
+        commands.resolve(&["token"]).unwrap(),
+        vec![KeyValue::new("token", "abc")]
+    );
+    assert_eq!(std::fs::read_to_string(&counter).unwrap(), "run\n");
+
+    let error = commands.resolve(&["failing"]).unwrap_err();
+    assert!(format!("{:#}", error).contains("oops"));
+}
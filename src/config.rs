@@ -1,17 +1,24 @@
 use serde::Deserialize;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub request_definition_directory: String,
     pub environment_directory: String,
     pub history_file: String,
+    pub session_file: Option<String>,
+    pub dotenv_file: Option<String>,
+    pub cookie_jar: Option<String>,
+    pub cookie_jar_per_environment: Option<bool>,
+    pub token_cache_file: Option<String>,
     pub theme: Option<String>,
     pub connect_timeout_seconds: Option<u64>,
     pub read_timeout_seconds: Option<u64>,
     pub timeout_seconds: Option<u64>,
     pub max_history_items: Option<u64>,
+    pub secret_patterns: Option<Vec<String>>,
+    pub key_file: Option<String>,
     pub colors: Option<CustomColors>,
 }
 
@@ -24,17 +31,31 @@ impl Config {
     }
 }
 
+/// Where a file that keeps state between runs, like the session file or the token cache, is
+/// stored: `location` if it's configured, otherwise `default`. State files are keyed by environment
+/// name, so that values saved while using one environment are never sent to another.
+pub fn state_file_path(location: Option<&str>, default: &str) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(location.unwrap_or(default)).as_ref())
+}
+
 impl Default for Config {
     fn default() -> Config {
         Config {
             request_definition_directory: "~/rhc/definitions".to_string(),
             environment_directory: "~/rhc/environments".to_string(),
             history_file: "~/.rhc_history".to_string(),
+            session_file: None,
+            dotenv_file: None,
+            cookie_jar: None,
+            cookie_jar_per_environment: None,
+            token_cache_file: None,
             theme: None,
             connect_timeout_seconds: None,
             read_timeout_seconds: None,
             timeout_seconds: None,
             max_history_items: None,
+            secret_patterns: None,
+            key_file: None,
             colors: None,
         }
     }
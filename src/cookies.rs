@@ -0,0 +1,362 @@
+use crate::config::Config;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use url::Url;
+
+/// A cookie received in a Set-Cookie header. Only the attributes that affect which requests the
+/// cookie is sent with are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+
+    /// If the Set-Cookie header had no Domain attribute, the cookie is only sent to the exact host
+    /// that set it, and not its subdomains.
+    pub host_only: bool,
+
+    pub path: String,
+
+    /// Unix timestamp. Cookies without one are kept until they're replaced or the jar is cleared.
+    pub expires: Option<i64>,
+
+    pub secure: bool,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, url: &Url, now: i64) -> bool {
+        let host = url.host_str().unwrap_or("").to_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && !self.is_expired(now)
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+// The directory of the request path, used when a cookie has no Path attribute
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+fn parse_expires(value: &str) -> Option<i64> {
+    // The standard format is like "Wed, 21 Oct 2015 07:28:00 GMT", but some servers still use the
+    // older formats with dashes
+    DateTime::parse_from_rfc2822(value)
+        .map(|d| d.timestamp())
+        .ok()
+        .or_else(|| {
+            ["%A, %d-%b-%y %H:%M:%S GMT", "%a, %d-%b-%Y %H:%M:%S GMT"]
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+                .map(|d| d.and_utc().timestamp())
+        })
+}
+
+/// Parse a Set-Cookie header received in response to a request for `url`. Returns None if the
+/// header is malformed, or sets a cookie for a domain that the URL isn't allowed to.
+pub fn parse_set_cookie(header: &str, url: &Url, now: i64) -> Option<Cookie> {
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let host = url.host_str()?.to_lowercase();
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.trim().to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url),
+        expires: None,
+        secure: false,
+    };
+
+    let mut max_age: Option<i64> = None;
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (attribute.trim(), ""),
+        };
+
+        match key.to_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_lowercase();
+                if !domain_matches(&host, &domain) {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "expires" => {
+                if let Some(expires) = parse_expires(value) {
+                    cookie.expires = Some(expires);
+                }
+            }
+            "max-age" => max_age = value.parse().ok(),
+            "secure" => cookie.secure = true,
+            _ => {}
+        }
+    }
+
+    // Max-Age takes precedence over Expires
+    if let Some(max_age) = max_age {
+        cookie.expires = Some(now + max_age.max(0));
+    }
+
+    Some(cookie)
+}
+
+/// Cookies saved between runs. If configured to be scoped per environment, each environment has
+/// its own set of cookies.
+pub struct CookieJar {
+    path: PathBuf,
+    scope: String,
+
+    // Scope -> cookies
+    jars: BTreeMap<String, Vec<Cookie>>,
+}
+
+impl CookieJar {
+    /// Load the cookie jar specified in the Config. Returns None if no cookie jar is configured,
+    /// since using one is opt-in.
+    pub fn load(config: &Config, env_name: &str) -> anyhow::Result<Option<CookieJar>> {
+        let location = match &config.cookie_jar {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+        let path = PathBuf::from(shellexpand::tilde(location).as_ref());
+
+        let jars: BTreeMap<String, Vec<Cookie>> = if path.is_file() {
+            let contents = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&contents).with_context(|| {
+                format!("Failed to parse cookie jar at {}", path.to_string_lossy())
+            })?
+        } else {
+            BTreeMap::new()
+        };
+
+        let scope = if config.cookie_jar_per_environment.unwrap_or(false) {
+            env_name.to_string()
+        } else {
+            String::new()
+        };
+
+        let mut jar = CookieJar { path, scope, jars };
+        jar.remove_expired(Utc::now().timestamp());
+        Ok(Some(jar))
+    }
+
+    fn remove_expired(&mut self, now: i64) {
+        for cookies in self.jars.values_mut() {
+            cookies.retain(|c| !c.is_expired(now));
+        }
+    }
+
+    /// The cookies in the current scope
+    pub fn cookies(&self) -> &[Cookie] {
+        self.jars.get(&self.scope).map_or(&[], |c| c.as_slice())
+    }
+
+    /// The value for a Cookie header to send with a request to `url`, if any cookies match it.
+    /// Cookies with longer paths are listed first.
+    pub fn header_for(&self, url: &Url) -> Option<String> {
+        let now = Utc::now().timestamp();
+        let mut matching: Vec<&Cookie> = self
+            .cookies()
+            .iter()
+            .filter(|c| c.matches(url, now))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        Some(
+            matching
+                .iter()
+                .map(|c| format!("{}={}", c.name, c.value))
+                .collect::<Vec<String>>()
+                .join("; "),
+        )
+    }
+
+    /// Record the cookies set by a response to a request for `url`. A cookie replaces any existing
+    /// one with the same name, domain and path; an already-expired cookie just removes it.
+    pub fn store<'a, I>(&mut self, url: &Url, set_cookie_headers: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let now = Utc::now().timestamp();
+        let cookies = self.jars.entry(self.scope.clone()).or_default();
+
+        for header in set_cookie_headers {
+            if let Some(cookie) = parse_set_cookie(header, url, now) {
+                cookies.retain(|c| {
+                    !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+                });
+                if !cookie.is_expired(now) {
+                    cookies.push(cookie);
+                }
+            }
+        }
+    }
+
+    /// Remove all the cookies in the current scope
+    pub fn clear(&mut self) {
+        self.jars.remove(&self.scope);
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.jars)?;
+        std::fs::write(&self.path, contents).with_context(|| {
+            format!(
+                "Failed to write cookie jar at {}",
+                self.path.to_string_lossy()
+            )
+        })
+    }
+}
+
+#[test]
+fn test_parse_set_cookie() {
+    let url = Url::parse("https://api.example.com/v1/login").unwrap();
+    let now = 1_000_000;
+
+    let cookie = parse_set_cookie("session=abc123; Path=/; HttpOnly", &url, now).unwrap();
+    assert_eq!(
+        cookie,
+        Cookie {
+            name: "session".to_string(),
+            value: "abc123".to_string(),
+            domain: "api.example.com".to_string(),
+            host_only: true,
+            path: "/".to_string(),
+            expires: None,
+            secure: false,
+        }
+    );
+
+    let cookie = parse_set_cookie(
+        "a=b; Domain=.Example.com; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Secure",
+        &url,
+        now,
+    )
+    .unwrap();
+    assert_eq!(cookie.domain, "example.com");
+    assert!(!cookie.host_only);
+    assert_eq!(cookie.path, "/v1");
+    assert_eq!(cookie.expires, Some(1_445_412_480));
+    assert!(cookie.secure);
+
+    let cookie = parse_set_cookie(
+        "a=b; Max-Age=60; Expires=Wed, 21 Oct 2015 07:28:00 GMT",
+        &url,
+        now,
+    )
+    .unwrap();
+    assert_eq!(cookie.expires, Some(now + 60));
+
+    // Can't set cookies for unrelated domains
+    assert_eq!(parse_set_cookie("a=b; Domain=other.com", &url, now), None);
+    assert_eq!(parse_set_cookie("no_equals_sign", &url, now), None);
+}
+
+#[test]
+fn test_matching() {
+    let jar_url = Url::parse("http://example.com/api/login").unwrap();
+    let now = 1_000_000;
+    let cookies = [
+        parse_set_cookie("host=1; Path=/api", &jar_url, now).unwrap(),
+        parse_set_cookie("domain=2; Domain=example.com; Path=/", &jar_url, now).unwrap(),
+        parse_set_cookie("secure=3; Path=/; Secure", &jar_url, now).unwrap(),
+        parse_set_cookie("expired=4; Path=/; Max-Age=0", &jar_url, now).unwrap(),
+    ];
+    let matching = |url: &str| -> Vec<&str> {
+        let url = Url::parse(url).unwrap();
+        cookies
+            .iter()
+            .filter(|c| c.matches(&url, now))
+            .map(|c| c.name.as_str())
+            .collect()
+    };
+
+    assert_eq!(
+        matching("http://example.com/api/users"),
+        vec!["host", "domain"]
+    );
+    assert_eq!(matching("http://example.com/apiary"), vec!["domain"]);
+    assert_eq!(matching("http://sub.example.com/api"), vec!["domain"]);
+    assert_eq!(matching("http://notexample.com/api"), Vec::<&str>::new());
+    assert_eq!(
+        matching("https://example.com/api"),
+        vec!["host", "domain", "secure"]
+    );
+}
+
+#[test]
+fn test_jar_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        cookie_jar: Some(dir.path().join("cookies").to_string_lossy().into_owned()),
+        cookie_jar_per_environment: Some(true),
+        ..Config::default()
+    };
+    let url = Url::parse("http://example.com/login").unwrap();
+
+    let mut jar = CookieJar::load(&config, "staging").unwrap().unwrap();
+    jar.store(&url, vec!["a=1; Path=/", "b=2; Path=/login"]);
+    jar.store(&url, vec!["a=replaced; Path=/"]);
+    jar.save().unwrap();
+
+    let jar = CookieJar::load(&config, "staging").unwrap().unwrap();
+    assert_eq!(jar.header_for(&url), Some("b=2; a=replaced".to_string()));
+    assert_eq!(
+        jar.header_for(&Url::parse("http://example.com/other").unwrap()),
+        Some("a=replaced".to_string())
+    );
+
+    // Scoped per environment
+    let mut jar = CookieJar::load(&config, "prod").unwrap().unwrap();
+    assert_eq!(jar.header_for(&url), None);
+
+    jar.store(&url, vec!["c=3"]);
+    jar.clear();
+    assert!(jar.cookies().is_empty());
+
+    assert!(CookieJar::load(&Config::default(), "staging")
+        .unwrap()
+        .is_none());
+}
@@ -0,0 +1,42 @@
+use crate::keyvalue;
+use crate::keyvalue::KeyValue;
+use anyhow::Context;
+use std::path::Path;
+
+/// Load the variables in a dotenv file, sorted by name. The process environment isn't changed;
+/// instead, `{$env.NAME}` looks in these before the process environment.
+pub fn load(path: &Path) -> anyhow::Result<Vec<KeyValue>> {
+    let context = || format!("Could not load dotenv file at {}", path.display());
+    let mut vars: Vec<KeyValue> = vec![];
+
+    for item in dotenvy::from_path_iter(path).with_context(context)? {
+        let (name, value) = item.with_context(context)?;
+        keyvalue::merge(&mut vars, vec![KeyValue { name, value }]);
+    }
+
+    Ok(vars)
+}
+
+#[test]
+fn test_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".env");
+    std::fs::write(
+        &path,
+        "# A comment\nRHC_TEST_B=\"two words\"\nexport RHC_TEST_A=1\nRHC_TEST_C='${RHC_TEST_A}'\nRHC_TEST_A=2\n",
+    )
+    .unwrap();
+
+    let vars = load(&path).unwrap();
+    assert_eq!(
+        vars,
+        vec![
+            KeyValue::new("RHC_TEST_A", "2"),
+            KeyValue::new("RHC_TEST_B", "two words"),
+            KeyValue::new("RHC_TEST_C", "${RHC_TEST_A}"),
+        ]
+    );
+    assert!(std::env::var_os("RHC_TEST_B").is_none());
+
+    assert!(load(&dir.path().join("missing")).is_err());
+}
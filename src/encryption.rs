@@ -0,0 +1,189 @@
+use crate::config::Config;
+use anyhow::{anyhow, Context};
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use lazy_static::lazy_static;
+use rand::Rng;
+use regex::{Captures, Regex};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Encrypted values start with this, followed by the base64-encoded salt, nonce and ciphertext
+pub const PREFIX: &str = "enc:v1:";
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+lazy_static! {
+    static ref ENCRYPTED_RE: Regex = Regex::new(r"enc:v1:[A-Za-z0-9+/=]+").unwrap();
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+/// A passphrase read from a key file, for encrypting and decrypting values in environment files.
+/// The actual encryption keys are derived from the passphrase with PBKDF2, using a random salt
+/// that's stored along with each value.
+pub struct Key {
+    passphrase: String,
+
+    // The salt for values encrypted with this Key, so the key only has to be derived once
+    salt: [u8; SALT_LENGTH],
+
+    // Salt -> derived key
+    derived: HashMap<[u8; SALT_LENGTH], [u8; 32]>,
+}
+
+impl Key {
+    /// Load the key file specified in the Config
+    pub fn load(config: &Config) -> anyhow::Result<Key> {
+        let location = config.key_file.as_deref().unwrap_or("~/.rhc_key");
+        Key::from_file(Path::new(shellexpand::tilde(location).as_ref()))
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Key> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the key file at {}", path.display()))?;
+
+        Key::new(contents.trim_end_matches(&['\r', '\n'][..]))
+    }
+
+    pub fn new(passphrase: &str) -> anyhow::Result<Key> {
+        if passphrase.is_empty() {
+            return Err(anyhow!("The key file is empty"));
+        }
+
+        Ok(Key {
+            passphrase: passphrase.to_string(),
+            salt: rand::thread_rng().gen(),
+            derived: HashMap::new(),
+        })
+    }
+
+    fn cipher(&mut self, salt: [u8; SALT_LENGTH]) -> ChaCha20Poly1305 {
+        let passphrase = &self.passphrase;
+        let key = self.derived.entry(salt).or_insert_with(|| {
+            let mut key = [0; 32];
+            pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &salt, PBKDF2_ROUNDS, &mut key);
+            key
+        });
+
+        ChaCha20Poly1305::new(&(*key).into())
+    }
+
+    pub fn encrypt(&mut self, plaintext: &str) -> anyhow::Result<String> {
+        let salt = self.salt;
+        let nonce: [u8; NONCE_LENGTH] = rand::thread_rng().gen();
+        let ciphertext = self
+            .cipher(salt)
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| anyhow!("Encryption failed"))?;
+
+        let mut bytes = salt.to_vec();
+        bytes.extend_from_slice(&nonce);
+        bytes.extend(ciphertext);
+
+        Ok(format!(
+            "{}{}",
+            PREFIX,
+            base64::engine::general_purpose::STANDARD.encode(bytes)
+        ))
+    }
+
+    pub fn decrypt(&mut self, value: &str) -> anyhow::Result<String> {
+        let encoded = value
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| anyhow!("The value isn't encrypted"))?;
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .context("The encrypted value isn't valid base64")?;
+        if bytes.len() < SALT_LENGTH + NONCE_LENGTH {
+            return Err(anyhow!("The encrypted value is too short"));
+        }
+
+        let (salt, rest) = bytes.split_at(SALT_LENGTH);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+        let mut salt_array = [0; SALT_LENGTH];
+        salt_array.copy_from_slice(salt);
+
+        let plaintext = self
+            .cipher(salt_array)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Decryption failed, the key file is probably not the one the value was encrypted with"))?;
+
+        String::from_utf8(plaintext).context("The decrypted value isn't valid UTF-8")
+    }
+}
+
+/// Re-encrypt every encrypted value in the contents of an environment file with a new key, leaving
+/// everything else as it is. Returns the new contents and the number of values re-encrypted.
+pub fn rekey(contents: &str, old: &mut Key, new: &mut Key) -> anyhow::Result<(String, usize)> {
+    let mut count = 0;
+    let mut error: Option<anyhow::Error> = None;
+    let rekeyed = ENCRYPTED_RE.replace_all(contents, |caps: &Captures| {
+        match old.decrypt(&caps[0]).and_then(|value| new.encrypt(&value)) {
+            Ok(value) => {
+                count += 1;
+                value
+            }
+            Err(e) => {
+                error.get_or_insert(e);
+                caps[0].to_string()
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok((rekeyed.into_owned(), count)),
+    }
+}
+
+#[test]
+fn test_encryption() {
+    let mut key = Key::new("correct horse battery staple").unwrap();
+    let encrypted = key.encrypt("s3cr3t {token}").unwrap();
+    assert!(is_encrypted(&encrypted));
+    assert!(!encrypted.contains("s3cr3t"));
+
+    // Each value gets its own nonce
+    assert_ne!(encrypted, key.encrypt("s3cr3t {token}").unwrap());
+
+    assert_eq!(key.decrypt(&encrypted).unwrap(), "s3cr3t {token}");
+
+    // A new Key with the same passphrase can decrypt it, but not one with a different passphrase
+    let mut same = Key::new("correct horse battery staple").unwrap();
+    assert_eq!(same.decrypt(&encrypted).unwrap(), "s3cr3t {token}");
+    let mut other = Key::new("wrong").unwrap();
+    assert!(other.decrypt(&encrypted).is_err());
+
+    assert!(key.decrypt("enc:v1:AAAA").is_err());
+    assert!(key.decrypt("plain").is_err());
+    assert!(Key::new("").is_err());
+}
+
+#[test]
+fn test_rekey() {
+    let mut old = Key::new("old").unwrap();
+    let mut new = Key::new("new").unwrap();
+    let contents = format!(
+        "variables = [\n  {{ name = \"a\", value = \"{}\" }},\n  {{ name = \"b\", value = \"plain\" }},\n]\n",
+        old.encrypt("first").unwrap()
+    );
+
+    let (rekeyed, count) = rekey(&contents, &mut old, &mut new).unwrap();
+    assert_eq!(count, 1);
+    assert!(rekeyed.contains("value = \"plain\""));
+
+    let value = ENCRYPTED_RE.find(&rekeyed).unwrap().as_str();
+    assert_eq!(new.decrypt(value).unwrap(), "first");
+    assert!(old.decrypt(value).is_err());
+
+    // Rekeying with the wrong old key fails
+    let mut wrong = Key::new("wrong").unwrap();
+    assert!(rekey(&contents, &mut wrong, &mut new).is_err());
+}
@@ -1,25 +1,179 @@
+use crate::commands::CommandVariable;
+use crate::config::Config;
+use crate::encryption::{self, Key};
 use crate::keyvalue::KeyValue;
-use anyhow::anyhow;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::path::Path;
+use crate::request_definition::Auth;
+use crate::templating;
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 #[derive(Deserialize, Debug)]
 pub struct Environment {
     pub name: String,
+
+    /// Another environment file that this one builds on, relative to the environment directory.
+    /// Anything bound in this file overrides what's bound there.
+    pub extends: Option<String>,
+
+    /// Set for environments that only exist to be extended, which aren't offered in interactive
+    /// mode. Not inherited by the environments that extend them.
+    #[serde(default, rename = "abstract")]
+    pub is_abstract: bool,
+
+    /// Either a list of `{ name, value }` tables, or a table of names to values of any type
+    #[serde(default, deserialize_with = "parse_variables")]
     pub variables: Vec<KeyValue>,
+
+    /// Variables whose values come from running a command, only when they're needed
+    #[serde(default)]
+    pub commands: Vec<CommandVariable>,
+
+    /// The names of variables whose values are secret
+    #[serde(default)]
+    pub secrets: Vec<String>,
+
+    /// Used for request definitions that don't have their own auth table
+    pub auth: Option<Auth>,
 }
 
 impl Environment {
-    pub fn new(path: &Path) -> anyhow::Result<Environment> {
+    /// Load an environment file, along with the environment files that it extends (and that those
+    /// extend, and so on), and decrypt any encrypted variable values.
+    pub fn new(path: &Path, config: &Config) -> anyhow::Result<Environment> {
+        let mut environment = Environment::parse(path, config)?;
+        environment.decrypt(config)?;
+        Ok(environment)
+    }
+
+    /// Load an environment file and the ones it extends like `new`, but leave any encrypted values
+    /// as they are. Used for listing environments, so that only the selected one is decrypted.
+    pub fn parse(path: &Path, config: &Config) -> anyhow::Result<Environment> {
+        Environment::load(path, config, &mut vec![])
+    }
+
+    /// Decrypt any encrypted variable values with the key file from the Config. The key file is
+    /// only needed if there are encrypted values.
+    pub fn decrypt(&mut self, config: &Config) -> anyhow::Result<()> {
+        // Decrypted values are used exactly as they are, and are always secret
+        let mut key: Option<Key> = None;
+        for var in &mut self.variables {
+            if encryption::is_encrypted(&var.value) {
+                let key = match &mut key {
+                    Some(key) => key,
+                    None => key.insert(Key::load(config)?),
+                };
+                let value = key
+                    .decrypt(&var.value)
+                    .with_context(|| format!("Could not decrypt the value of {}", var.name))?;
+
+                var.value = templating::escape(&value);
+                self.secrets.push(var.name.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    // `stack` is the files that are currently being loaded, for detecting cycles
+    fn load(path: &Path, config: &Config, stack: &mut Vec<PathBuf>) -> anyhow::Result<Environment> {
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("Could not find {}", path.display()))?;
+        if let Some(start) = stack.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = stack[start..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(anyhow!(
+                "Environment files extend each other in a cycle: {}",
+                cycle.join(" -> ")
+            ));
+        }
+
+        let environment = Environment::layer(path)?;
+        let base_path = match environment.base_path(config) {
+            Some(base_path) => base_path,
+            None => return Ok(environment),
+        };
+
+        stack.push(canonical);
+        let base = Environment::load(&base_path, config, stack).with_context(|| {
+            format!(
+                "Failed to load {}, which {} extends",
+                base_path.display(),
+                path.display()
+            )
+        })?;
+        stack.pop();
+
+        Ok(environment.over(base))
+    }
+
+    /// The path of the environment file that this one extends, if any
+    fn base_path(&self, config: &Config) -> Option<PathBuf> {
+        self.extends.as_ref().map(|base_name| {
+            PathBuf::from(shellexpand::tilde(&config.environment_directory).as_ref())
+                .join(shellexpand::tilde(base_name).as_ref())
+        })
+    }
+
+    /// Layer this environment over `base`. Variables and commands in this environment replace the
+    /// ones with the same name in `base`, and its auth table replaces the one in `base` if it has
+    /// one.
+    pub fn over(self, base: Environment) -> Environment {
+        let names: HashSet<String> = self
+            .variables
+            .iter()
+            .map(|var| var.name.clone())
+            .chain(self.commands.iter().map(|command| command.name.clone()))
+            .collect();
+
+        let mut variables: Vec<KeyValue> = base
+            .variables
+            .into_iter()
+            .filter(|var| !names.contains(&var.name))
+            .collect();
+        variables.extend(self.variables);
+
+        let mut commands: Vec<CommandVariable> = base
+            .commands
+            .into_iter()
+            .filter(|command| !names.contains(&command.name))
+            .collect();
+        commands.extend(self.commands);
+
+        let mut secrets = base.secrets;
+        secrets.extend(self.secrets);
+
+        Environment {
+            name: self.name,
+            extends: self.extends,
+            is_abstract: self.is_abstract,
+            variables,
+            commands,
+            secrets,
+            auth: self.auth.or(base.auth),
+        }
+    }
+
+    // Load a single environment file, without anything that it extends
+    fn layer(path: &Path) -> anyhow::Result<Environment> {
         let contents = std::fs::read_to_string(path)?;
 
         let environment: Environment = toml::from_str(&contents)?;
 
-        // Disallow duplicate variable definitions
+        // Disallow duplicate variable definitions within a layer. Across layers, they're overrides.
         let mut counts: HashMap<&str, u32> = HashMap::new();
-        for var in &environment.variables {
-            *counts.entry(&var.name).or_insert(0) += 1;
+        for name in environment
+            .variables
+            .iter()
+            .map(|var| &var.name)
+            .chain(environment.commands.iter().map(|command| &command.name))
+        {
+            *counts.entry(name).or_insert(0) += 1;
         }
 
         let dupes: Vec<&str> = counts
@@ -28,13 +182,218 @@ impl Environment {
             .map(|(name, _)| name)
             .collect();
         if !dupes.is_empty() {
-            Err(anyhow!(
+            return Err(anyhow!(
                 "The environment file {} contains duplicate bindings for: {}",
                 path.to_string_lossy(),
                 dupes.join(", ")
-            ))
-        } else {
-            Ok(environment)
+            ));
         }
+
+        Ok(environment)
+    }
+}
+
+fn parse_variables<'de, D>(deserializer: D) -> Result<Vec<KeyValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match toml::Value::deserialize(deserializer)? {
+        list @ toml::Value::Array(_) => list.try_into().map_err(serde::de::Error::custom),
+        toml::Value::Table(table) => Ok(table
+            .into_iter()
+            .map(|(name, value)| KeyValue {
+                name,
+                value: variable_value(value),
+            })
+            .collect()),
+        _ => Err(serde::de::Error::custom(
+            "variables must be a list of name/value pairs, or a table",
+        )),
     }
 }
+
+// Strings are bound as they are, so they can still use other variables like the values in the
+// list form. Datetimes are bound as they're written, and arrays and tables as their JSON
+// representation, which are both used exactly as they are.
+fn variable_value(value: toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s,
+        toml::Value::Datetime(d) => templating::escape(&d.to_string()),
+        other => templating::escape(&to_json(other).to_string()),
+    }
+}
+
+fn to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(s) => serde_json::Value::String(s),
+        toml::Value::Integer(i) => serde_json::Value::from(i),
+        toml::Value::Float(f) => serde_json::Value::from(f),
+        toml::Value::Boolean(b) => serde_json::Value::Bool(b),
+        toml::Value::Datetime(d) => serde_json::Value::String(d.to_string()),
+        toml::Value::Array(values) => values.into_iter().map(to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, to_json(value)))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_variables_table() {
+    let environment: Environment = toml::from_str(
+        r#"
+        name = "test"
+
+        [variables]
+        host = "{subdomain}.example.com"
+        port = 8080
+        ratio = 0.5
+        debug = true
+        tags = ["a", "b", 1]
+        limits = { max = 10, names = ["x"] }
+        when = 1979-05-27T07:32:00Z
+        dates = [1979-05-27]
+        "#,
+    )
+    .unwrap();
+
+    let mut variables = environment.variables;
+    variables.sort();
+    assert_eq!(
+        variables,
+        vec![
+            KeyValue::new("dates", r#"["1979-05-27"]"#),
+            KeyValue::new("debug", "true"),
+            KeyValue::new("host", "{subdomain}.example.com"),
+            KeyValue::new("limits", r#"\{"max":10,"names":["x"]\}"#),
+            KeyValue::new("port", "8080"),
+            KeyValue::new("ratio", "0.5"),
+            KeyValue::new("tags", r#"["a","b",1]"#),
+            KeyValue::new("when", "1979-05-27T07:32:00Z"),
+        ]
+    );
+
+    // The list form still works
+    let environment: Environment = toml::from_str(
+        r#"
+        name = "test"
+        variables = [{ name = "port", value = "8080" }]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(environment.variables, vec![KeyValue::new("port", "8080")]);
+
+    assert!(toml::from_str::<Environment>("name = \"test\"\nvariables = 5").is_err());
+    assert!(toml::from_str::<Environment>("name = \"test\"\nvariables = [5]").is_err());
+}
+
+#[test]
+fn test_extends() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        environment_directory: dir.path().to_string_lossy().to_string(),
+        ..Config::default()
+    };
+    let write =
+        |name: &str, contents: &str| std::fs::write(dir.path().join(name), contents).unwrap();
+
+    write(
+        "base.toml",
+        r#"
+        name = "base"
+        variables = [
+          { name = "host", value = "localhost" },
+          { name = "port", value = "8080" },
+        ]
+        commands = [{ name = "token", command = "echo base" }]
+        secrets = ["token"]
+        auth = { type = "bearer", token = "{token}" }
+        "#,
+    );
+    write(
+        "staging.toml",
+        r#"
+        name = "staging"
+        extends = "base.toml"
+        variables = [
+          { name = "host", value = "staging.example.com" },
+          { name = "token", value = "fixed" },
+        ]
+        "#,
+    );
+
+    let env = Environment::new(&dir.path().join("staging.toml"), &config).unwrap();
+    assert_eq!(env.name, "staging");
+    let mut variables = env.variables.clone();
+    variables.sort();
+    assert_eq!(
+        variables,
+        vec![
+            KeyValue::new("host", "staging.example.com"),
+            KeyValue::new("port", "8080"),
+            KeyValue::new("token", "fixed"),
+        ]
+    );
+    assert!(env.commands.is_empty());
+    assert_eq!(env.secrets, vec!["token"]);
+    assert!(env.auth.is_some());
+
+    // Duplicates are only allowed across layers
+    write(
+        "dupes.toml",
+        r#"
+        name = "dupes"
+        extends = "base.toml"
+        variables = [
+          { name = "host", value = "a" },
+          { name = "host", value = "b" },
+        ]
+        "#,
+    );
+    let err = Environment::new(&dir.path().join("dupes.toml"), &config).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("dupes.toml contains duplicate bindings for: host"));
+
+    write("a.toml", "name = \"a\"\nextends = \"b.toml\"\n");
+    write("b.toml", "name = \"b\"\nextends = \"a.toml\"\n");
+    let err = Environment::new(&dir.path().join("a.toml"), &config).unwrap_err();
+    let message = format!("{:#}", err);
+    assert!(message.contains("Environment files extend each other in a cycle"));
+    assert!(message.contains("a.toml -> "));
+}
+
+#[test]
+fn test_parse_without_decrypting() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_file = dir.path().join("key");
+    let config = Config {
+        key_file: Some(key_file.to_string_lossy().to_string()),
+        ..Config::default()
+    };
+
+    let encrypted = Key::new("passphrase").unwrap().encrypt("s3cr3t").unwrap();
+    let path = dir.path().join("env.toml");
+    std::fs::write(
+        &path,
+        format!(
+            "name = \"env\"\nvariables = [{{ name = \"token\", value = \"{}\" }}]\n",
+            encrypted
+        ),
+    )
+    .unwrap();
+
+    // The key file is only needed for decrypting
+    let mut environment = Environment::parse(&path, &config).unwrap();
+    assert_eq!(environment.variables[0].value, encrypted);
+    assert!(environment.secrets.is_empty());
+    assert!(Environment::new(&path, &config).is_err());
+
+    std::fs::write(&key_file, "passphrase\n").unwrap();
+    environment.decrypt(&config).unwrap();
+    assert_eq!(
+        environment.variables,
+        vec![KeyValue::new("token", "s3cr3t")]
+    );
+    assert_eq!(environment.secrets, vec!["token"]);
+}
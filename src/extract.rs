@@ -0,0 +1,101 @@
+use crate::http::CapturedResponse;
+use crate::json_path;
+use crate::keyvalue::KeyValue;
+use crate::request_definition::{Extraction, ExtractionSource};
+use anyhow::anyhow;
+use regex::Regex;
+
+/// Pull the values described by `extractions` out of a response. Fails if any of them can't be
+/// found, so that a later request never silently runs with a missing value.
+pub fn extract_values(
+    extractions: &[Extraction],
+    response: &CapturedResponse,
+) -> anyhow::Result<Vec<KeyValue>> {
+    // Only parse the body as JSON if it's actually needed
+    let mut json: Option<serde_json::Value> = None;
+
+    let mut result: Vec<KeyValue> = Vec::new();
+    for extraction in extractions {
+        let value: String = match &extraction.source {
+            ExtractionSource::Status => response.status.as_u16().to_string(),
+            ExtractionSource::Header { header } => response
+                .headers
+                .get(header.as_str())
+                .ok_or_else(|| anyhow!("The response has no header named {}", header))?
+                .to_str()?
+                .to_string(),
+            ExtractionSource::Json { path } => {
+                if json.is_none() {
+                    json = Some(serde_json::from_str(&response.body).map_err(|e| {
+                        anyhow!("Could not parse the response body as JSON: {}", e)
+                    })?);
+                }
+                let found = json_path::lookup(json.as_ref().unwrap(), path)
+                    .ok_or_else(|| anyhow!("Nothing found at JSON path {}", path))?;
+                json_path::to_plain_string(found)
+            }
+            ExtractionSource::Regex { pattern, group } => {
+                let re = Regex::new(pattern)?;
+                let captures = re
+                    .captures(&response.body)
+                    .ok_or_else(|| anyhow!("The response body does not match {}", pattern))?;
+
+                // Default to the first capture group if there is one, otherwise the whole match
+                let group = group.unwrap_or(if captures.len() > 1 { 1 } else { 0 });
+                captures
+                    .get(group)
+                    .ok_or_else(|| anyhow!("The regex {} has no group {}", pattern, group))?
+                    .as_str()
+                    .to_string()
+            }
+        };
+
+        result.push(KeyValue::new(&extraction.name, &value));
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_extract_values() {
+    let mut headers = attohttpc::header::HeaderMap::new();
+    headers.insert("x-request-id", "req-123".parse().unwrap());
+    let response = CapturedResponse {
+        status: attohttpc::StatusCode::CREATED,
+        headers,
+        body: r#"{"data": {"token": "abc", "count": 3}, "html": "value=\"xyz\""}"#.to_string(),
+    };
+
+    let extractions: Vec<Extraction> = toml::from_str::<crate::request_definition::Extract>(
+        r#"
+        values = [
+          { name = "token", type = "json", path = "data.token" },
+          { name = "count", type = "json", path = "$.data.count" },
+          { name = "request_id", type = "header", header = "X-Request-Id" },
+          { name = "code", type = "status" },
+          { name = "csrf", type = "regex", pattern = 'value=\\"(\w+)' },
+        ]
+        "#,
+    )
+    .unwrap()
+    .values;
+
+    let values = extract_values(&extractions, &response).unwrap();
+    assert_eq!(
+        values,
+        vec![
+            KeyValue::new("token", "abc"),
+            KeyValue::new("count", "3"),
+            KeyValue::new("request_id", "req-123"),
+            KeyValue::new("code", "201"),
+            KeyValue::new("csrf", "xyz"),
+        ]
+    );
+
+    let missing: Vec<Extraction> = toml::from_str::<crate::request_definition::Extract>(
+        r#"values = [{ name = "x", type = "json", path = "data.missing" }]"#,
+    )
+    .unwrap()
+    .values;
+    assert!(extract_values(&missing, &response).is_err());
+}
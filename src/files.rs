@@ -47,16 +47,52 @@ pub fn list_all_choices(config: &Config) -> Vec<Choice> {
 }
 
 /// Try to load all environments from TOML files under the base environments directory. If any are
-/// invalid, display a message on stderr and don't allow them to be used.
+/// invalid, display a message on stderr and don't allow them to be used. Encrypted values aren't
+/// decrypted, so the key file isn't needed until an environment is selected. Abstract environments
+/// are left out, since they're only there to be extended.
 pub fn list_all_environments(config: &Config) -> Vec<(Environment, PathBuf)> {
     get_all_toml_files(&config.environment_directory)
         .into_iter()
-        .filter_map(|path| match Environment::new(&path) {
+        .filter_map(|path| match Environment::parse(&path, config) {
             Ok(env) => Some((env, path)),
             Err(err) => {
                 eprintln!("Error: {}", err);
                 None
             }
         })
+        .filter(|(env, _)| !env.is_abstract)
         .collect()
 }
+
+#[test]
+fn test_list_all_environments() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        environment_directory: dir.path().to_string_lossy().to_string(),
+        ..Config::default()
+    };
+    std::fs::write(
+        dir.path().join("base.toml"),
+        "name = \"base\"\nabstract = true\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("staging.toml"),
+        "name = \"staging\"\nextends = \"base.toml\"\n",
+    )
+    .unwrap();
+
+    // Extending an environment doesn't hide it
+    std::fs::write(
+        dir.path().join("local.toml"),
+        "name = \"local\"\nextends = \"staging.toml\"\n",
+    )
+    .unwrap();
+
+    let mut names: Vec<String> = list_all_environments(&config)
+        .into_iter()
+        .map(|(env, _)| env.name)
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["local", "staging"]);
+}
@@ -1,8 +1,18 @@
+use crate::auth;
+use crate::commands::{CommandVariable, CommandVariables};
 use crate::config::Config;
-use crate::request_definition::{Content, RequestDefinition};
+use crate::cookies::CookieJar;
+use crate::keyvalue::KeyValue;
+use crate::multipart;
+use crate::oauth2;
+use crate::request_definition::{Auth, Content, FileContents, Headers, RequestDefinition};
+use anyhow::{anyhow, Context};
 use attohttpc::body;
-use attohttpc::Response;
+use attohttpc::header::HeaderMap;
+use attohttpc::{Response, StatusCode};
+use chrono::Utc;
 use std::time::Duration;
+use url::{Position, Url};
 
 // Wrapper around attohttpc's PreparedRequest, in order to
 // make the types simpler
@@ -12,7 +22,61 @@ enum OurPreparedRequest {
     Empty(attohttpc::PreparedRequest<body::Empty>),
 }
 
-fn prepare_request(def: RequestDefinition, config: &Config) -> anyhow::Result<OurPreparedRequest> {
+/// State shared by all the requests sent during one run of rhc
+pub struct RequestContext<'a> {
+    pub config: &'a Config,
+
+    /// The name of the environment in use, which cookies and OAuth2 tokens are stored under
+    pub env_name: String,
+
+    /// The environment's auth table, used for request definitions that don't have their own
+    pub default_auth: Option<Auth>,
+
+    /// The environment's command variables, whose values are kept for the whole run
+    pub commands: CommandVariables,
+
+    /// The variables from the dotenv file, which `{$env.NAME}` looks in first
+    pub dotenv: Vec<KeyValue>,
+
+    pub cookie_jar: Option<CookieJar>,
+}
+
+impl<'a> RequestContext<'a> {
+    pub fn new(
+        config: &'a Config,
+        env_name: &str,
+        default_auth: Option<Auth>,
+        commands: Vec<CommandVariable>,
+        dotenv: Vec<KeyValue>,
+    ) -> anyhow::Result<RequestContext<'a>> {
+        Ok(RequestContext {
+            config,
+            env_name: env_name.to_string(),
+            default_auth,
+            commands: CommandVariables::new(commands),
+            dotenv,
+            cookie_jar: CookieJar::load(config, env_name)?,
+        })
+    }
+
+    /// Persist anything that was updated while sending requests
+    pub fn save(&self) -> anyhow::Result<()> {
+        if let Some(jar) = &self.cookie_jar {
+            jar.save()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn prepare_request(
+    mut def: RequestDefinition,
+    config: &Config,
+    cookie_jar: Option<&CookieJar>,
+) -> anyhow::Result<OurPreparedRequest> {
+    // Normally this will already have been done before substituting variables
+    def.load_body_file()?;
+
     let mut request_builder =
         attohttpc::RequestBuilder::try_new(def.request.method.to_http_method(), &def.request.url)?;
 
@@ -37,26 +101,58 @@ fn prepare_request(def: RequestDefinition, config: &Config) -> anyhow::Result<Ou
         }
     }
 
+    match &def.auth {
+        Some(Auth::Basic { username, password }) => {
+            request_builder = request_builder.basic_auth(username, password.as_ref());
+        }
+        Some(Auth::Bearer { token }) => {
+            request_builder = request_builder.bearer_auth(token);
+        }
+        // Handled in send_request
+        Some(Auth::Digest { .. }) | Some(Auth::OAuth2(_)) => {}
+        // Handled in sign_and_prepare
+        Some(Auth::AwsSigV4(_)) | Some(Auth::Hmac(_)) | None => {}
+    }
+
     if let Some(query) = def.query {
         for param in query.params {
             request_builder = request_builder.param(param.name, param.value);
         }
     }
 
+    // A Cookie header in the definition takes precedence over the cookie jar
+    if let Some(jar) = cookie_jar {
+        let inspector = request_builder.inspect();
+        let cookies = if inspector.headers().contains_key(attohttpc::header::COOKIE) {
+            None
+        } else {
+            jar.header_for(inspector.url())
+        };
+
+        if let Some(cookies) = cookies {
+            request_builder = request_builder.try_header(attohttpc::header::COOKIE, cookies)?;
+        }
+    }
+
+    let auth = def.auth.as_ref();
     match def.body {
         None => {
-            let prepared = request_builder.try_prepare()?;
+            let prepared = sign_and_prepare(request_builder, auth)?;
             Ok(OurPreparedRequest::Empty(prepared))
         }
         Some(Content::Json(json_string)) => {
             // At this point, all variable substitutions have been made, so if the string content
             // can't be successfully parsed to JSON, this will return an Error.
             let json_value: serde_json::Value = serde_json::from_str(&json_string)?;
-            let prepared = request_builder.json(&json_value)?.try_prepare()?;
+            let prepared = sign_and_prepare(request_builder.json(&json_value)?, auth)?;
+            Ok(OurPreparedRequest::Bytes(prepared))
+        }
+        Some(Content::TypedJson(json_value)) => {
+            let prepared = sign_and_prepare(request_builder.json(&json_value)?, auth)?;
             Ok(OurPreparedRequest::Bytes(prepared))
         }
         Some(Content::Text(text)) => {
-            let prepared = request_builder.text(text).try_prepare()?;
+            let prepared = sign_and_prepare(request_builder.text(text), auth)?;
             Ok(OurPreparedRequest::Text(prepared))
         }
         Some(Content::UrlEncoded(form)) => {
@@ -66,12 +162,107 @@ fn prepare_request(def: RequestDefinition, config: &Config) -> anyhow::Result<Ou
                 .map(|keyvalue| (keyvalue.name, keyvalue.value))
                 .collect();
 
-            let prepared = request_builder.form(&tuples)?.try_prepare()?;
+            let prepared = sign_and_prepare(request_builder.form(&tuples)?, auth)?;
+            Ok(OurPreparedRequest::Bytes(prepared))
+        }
+        Some(Content::Multipart(parts)) => {
+            let body = multipart::encode(&parts, &def.path)?;
+            let request_builder = request_builder
+                .header(attohttpc::header::CONTENT_TYPE, body.content_type())
+                .bytes(body.bytes);
+            let prepared = sign_and_prepare(request_builder, auth)?;
             Ok(OurPreparedRequest::Bytes(prepared))
         }
+        Some(Content::File(file_body)) => {
+            if let Some(content_type) = file_body.content_type {
+                request_builder =
+                    request_builder.header(attohttpc::header::CONTENT_TYPE, content_type);
+            }
+
+            match file_body.contents {
+                Some(FileContents::Text(text)) => {
+                    let prepared = sign_and_prepare(request_builder.text(text), auth)?;
+                    Ok(OurPreparedRequest::Text(prepared))
+                }
+                Some(FileContents::Bytes(bytes)) => {
+                    let prepared = sign_and_prepare(request_builder.bytes(bytes), auth)?;
+                    Ok(OurPreparedRequest::Bytes(prepared))
+                }
+                None => Err(anyhow!("The request body file was not loaded")),
+            }
+        }
+    }
+}
+
+// The bytes of a request body, for signing
+trait BodyBytes {
+    fn body_bytes(&self) -> &[u8];
+}
+
+impl BodyBytes for body::Empty {
+    fn body_bytes(&self) -> &[u8] {
+        &[]
     }
 }
 
+impl BodyBytes for body::Text<String> {
+    fn body_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl BodyBytes for body::Bytes<Vec<u8>> {
+    fn body_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Sign the request if its auth table calls for it, then prepare it. This has to be the very last
+/// step, so that the signature covers the final URL, headers and body.
+fn sign_and_prepare<B: body::Body + BodyBytes>(
+    mut request_builder: attohttpc::RequestBuilder<B>,
+    auth: Option<&Auth>,
+) -> anyhow::Result<attohttpc::PreparedRequest<B>> {
+    match auth {
+        Some(Auth::AwsSigV4(settings)) => {
+            let mut inspector = request_builder.inspect();
+            let body = inspector.body().body_bytes().to_vec();
+            let signature_headers = auth::aws_sigv4_headers(
+                settings,
+                inspector.method().as_str(),
+                inspector.url(),
+                inspector.headers(),
+                &body,
+                Utc::now(),
+            );
+
+            for (name, value) in signature_headers {
+                request_builder = request_builder.try_header(name, value)?;
+            }
+        }
+        Some(Auth::Hmac(settings)) => {
+            let mut inspector = request_builder.inspect();
+            let body = inspector.body().body_bytes().to_vec();
+            let signature_headers = auth::hmac_headers(
+                settings,
+                inspector.method().as_str(),
+                inspector.url(),
+                inspector.headers(),
+                &body,
+                Utc::now(),
+            )?;
+
+            for (name, value) in signature_headers {
+                let name = attohttpc::header::HeaderName::from_bytes(name.as_bytes())?;
+                request_builder = request_builder.try_header(name, value)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(request_builder.try_prepare()?)
+}
+
 #[test]
 fn test_bad_files() {
     for entry in std::fs::read_dir("test_definitions/prepare_bad").unwrap() {
@@ -84,7 +275,7 @@ fn test_bad_files() {
             path.to_string_lossy()
         );
 
-        let prepared = prepare_request(def.unwrap(), &Config::default());
+        let prepared = prepare_request(def.unwrap(), &Config::default(), None);
         assert!(
             prepared.is_err(),
             "expected file {:?} to error on calling prepare_request, but it was OK",
@@ -93,14 +284,105 @@ fn test_bad_files() {
     }
 }
 
-pub fn send_request(def: RequestDefinition, config: &Config) -> anyhow::Result<Response> {
-    let prepared = prepare_request(def, config)?;
+/// Send the request, using and updating the context's cookie jar if there is one. The context still
+/// needs to be saved afterwards.
+pub fn send_request(
+    mut def: RequestDefinition,
+    context: &mut RequestContext,
+) -> anyhow::Result<Response> {
+    let config = context.config;
+
+    if let Some(Auth::OAuth2(settings)) = &def.auth {
+        let token = oauth2::access_token(settings, config, &context.env_name)
+            .context("Failed getting an OAuth2 access token")?;
+        def.auth = Some(Auth::Bearer { token });
+    }
+
+    // Digest credentials can only be sent in a second request, once the server's challenge is known
+    let digest_retry = match &def.auth {
+        Some(Auth::Digest { username, password }) => {
+            let mut retry = def.clone();
+            retry.auth = None;
+            Some((retry, username.clone(), password.clone()))
+        }
+        _ => None,
+    };
+
+    let (url, res) = send_once(def, config, context.cookie_jar.as_mut())?;
+
+    if let Some((mut retry, username, password)) = digest_retry {
+        let challenge = res
+            .headers()
+            .get_all(attohttpc::header::WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find(|value| value.trim_start().to_lowercase().starts_with("digest"));
+
+        if let (StatusCode::UNAUTHORIZED, Some(challenge)) = (res.status(), challenge) {
+            let authorization = auth::digest_authorization(
+                challenge,
+                &username,
+                &password,
+                retry.request.method.to_http_method().as_str(),
+                &url[Position::BeforePath..],
+            )?;
+            retry
+                .headers
+                .get_or_insert_with(|| Headers { headers: vec![] })
+                .headers
+                .push(KeyValue::new("Authorization", &authorization));
 
-    let res = match prepared {
-        OurPreparedRequest::Empty(mut req) => req.send(),
-        OurPreparedRequest::Text(mut req) => req.send(),
-        OurPreparedRequest::Bytes(mut req) => req.send(),
-    }?;
+            let (_, res) = send_once(retry, config, context.cookie_jar.as_mut())?;
+            return Ok(res);
+        }
+    }
 
     Ok(res)
 }
+
+fn send_once(
+    def: RequestDefinition,
+    config: &Config,
+    cookie_jar: Option<&mut CookieJar>,
+) -> anyhow::Result<(Url, Response)> {
+    let prepared = prepare_request(def, config, cookie_jar.as_deref())?;
+
+    let (url, res) = match prepared {
+        OurPreparedRequest::Empty(mut req) => (req.url().clone(), req.send()),
+        OurPreparedRequest::Text(mut req) => (req.url().clone(), req.send()),
+        OurPreparedRequest::Bytes(mut req) => (req.url().clone(), req.send()),
+    };
+    let res = res?;
+
+    if let Some(jar) = cookie_jar {
+        let set_cookies = res
+            .headers()
+            .get_all(attohttpc::header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok());
+        jar.store(&url, set_cookies);
+    }
+
+    Ok((url, res))
+}
+
+/// A response whose body has been read in full, so that it can be both displayed and inspected (for
+/// extracting values, etc.)
+pub struct CapturedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl CapturedResponse {
+    pub fn new(res: Response) -> anyhow::Result<CapturedResponse> {
+        let (status, headers, reader) = res.split();
+        let body = reader.text()?;
+
+        Ok(CapturedResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
@@ -3,7 +3,10 @@ use crate::config::Config;
 use crate::environment::Environment;
 use crate::files;
 use crate::keyvalue::KeyValue;
+use crate::request_definition::VariableDeclaration;
+use crate::secrets::Secrets;
 use crate::{colors::Colors, request_definition::RequestDefinition};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::OpenOptions;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
@@ -321,10 +324,16 @@ pub fn interactive_mode<R: std::io::Read, B: tui::backend::Backend + std::io::Wr
         Some(path) => {
             let def: RequestDefinition =
                 files::load_file(&path, RequestDefinition::new, "request definition")?;
-            let env: Option<Environment> = app_state
+            let mut env: Option<Environment> = app_state
                 .active_env_index
                 .map(|i| environments.remove(i))
                 .map(|(e, _)| e);
+
+            // Only the selected environment needs its values decrypted
+            if let Some(env) = &mut env {
+                env.decrypt(config)?;
+            }
+
             Some(SelectedValues { def, env })
         }
     };
@@ -340,6 +349,9 @@ struct PromptState {
     // are no filtered options to be selected, or the history pane is not active, meaning the user
     // is in "query input" move.
     active_history_item_index: Option<usize>,
+
+    // Why the last value entered wasn't accepted, shown until the next key press
+    error: Option<String>,
 }
 
 impl PromptState {
@@ -348,6 +360,7 @@ impl PromptState {
             query: String::new(),
             list_state: ListState::default(),
             active_history_item_index: None,
+            error: None,
         }
     }
 }
@@ -359,12 +372,27 @@ struct HistoryItem {
     env_name: String,
 }
 
+/// What's known about the variables being prompted for, besides their names
+pub struct VariableInfo<'a> {
+    /// Variables that are bound to their default value if the user enters nothing
+    pub defaults: &'a HashMap<&'a str, &'a str>,
+
+    /// Values for declared variables have to be valid before they're accepted, and any allowed
+    /// values they list are offered along with the history
+    pub declarations: &'a BTreeMap<String, VariableDeclaration>,
+
+    /// What's typed for a secret variable is masked, and neither offered from nor saved to the
+    /// history
+    pub secrets: &'a Secrets,
+}
+
 /// Given a list of unbound variable names, prompt the user to interactively enter values to bind
 /// them to, and return those created KeyValues. Returning None means the user aborted with Ctrl-C
 /// and we should not send the request.
 pub fn prompt_for_variables<R: std::io::Read, B: tui::backend::Backend + std::io::Write>(
     config: &Config,
     names: Vec<&str>,
+    info: &VariableInfo,
     env_name: &str,
     stdin: &mut Keys<R>,
     terminal: &mut Terminal<B>,
@@ -447,6 +475,23 @@ pub fn prompt_for_variables<R: std::io::Read, B: tui::backend::Backend + std::io
     // interactively
     let mut created_items: Vec<HistoryItem> = vec![];
 
+    // The allowed values of declared variables, which are offered like history items
+    let declared_items: Vec<HistoryItem> = names
+        .iter()
+        .flat_map(|name| {
+            info.declarations
+                .get(*name)
+                .and_then(|declaration| declaration.values.as_ref())
+                .into_iter()
+                .flatten()
+                .map(move |value| HistoryItem {
+                    name: name.to_string(),
+                    value: value.clone(),
+                    env_name: env_name.to_string(),
+                })
+        })
+        .collect();
+
     let highlight_symbol = ">> ";
 
     loop {
@@ -454,11 +499,26 @@ pub fn prompt_for_variables<R: std::io::Read, B: tui::backend::Backend + std::io
 
         // First, filter to just the history items that were used for this variable name and
         // environment
+        let declaration = info.declarations.get(names[current_name_index]);
+        let secret = info.secrets.contains(names[current_name_index]);
+        // Nothing is offered for secret variables, so that no values are shown
         let mut filtered_history_items: Vec<&HistoryItem> = full_history
             .iter()
             .filter(|item| item.name == names[current_name_index] && item.env_name == env_name)
+            .filter(|_| !secret)
+            // Values that the declaration doesn't allow (anymore) aren't offered
+            .filter(|item| declaration.is_none_or(|d| d.validate(&item.value).is_ok()))
             .collect();
 
+        for item in declared_items
+            .iter()
+            .filter(|item| item.name == names[current_name_index] && !secret)
+        {
+            if !filtered_history_items.iter().any(|h| h.value == item.value) {
+                filtered_history_items.push(item);
+            }
+        }
+
         // Fuzzy matching is basically the same as for choosing a request definition
         if !state.query.is_empty() {
             let mut matching_items: Vec<(isize, &HistoryItem)> = filtered_history_items
@@ -492,12 +552,39 @@ pub fn prompt_for_variables<R: std::io::Read, B: tui::backend::Backend + std::io
             .highlight_style(selected_style)
             .highlight_symbol(highlight_symbol);
 
-        let explanation_text = [
-            Text::raw("Enter a value for "),
-            Text::styled(names[current_name_index], variable_style),
-        ];
+        let explanation_text = match &state.error {
+            Some(error) => [
+                Text::raw("Invalid value for "),
+                Text::styled(names[current_name_index], variable_style),
+                Text::raw(format!(": {}", error)),
+            ],
+            None => {
+                let description = declaration.and_then(|d| d.description.as_ref());
+                let default = info.defaults.get(names[current_name_index]);
+                let details = match (description, default) {
+                    (Some(description), Some(default)) => {
+                        format!(": {} (default: {})", description, default)
+                    }
+                    (Some(description), None) => format!(": {}", description),
+                    (None, Some(default)) => format!(" (default: {})", default),
+                    (None, None) => String::new(),
+                };
+                [
+                    Text::raw("Enter a value for "),
+                    Text::styled(names[current_name_index], variable_style),
+                    Text::raw(details),
+                ]
+            }
+        };
         let explanation_widget = Paragraph::new(explanation_text.iter());
 
+        // What's typed for a secret variable is masked
+        let displayed_query = if secret {
+            "*".repeat(state.query.chars().count())
+        } else {
+            state.query.clone()
+        };
+
         terminal.draw(|mut f| {
             let width = f.size().width;
             let height = f.size().height;
@@ -523,7 +610,7 @@ pub fn prompt_for_variables<R: std::io::Read, B: tui::backend::Backend + std::io
             let query_rect = tui::layout::Rect::new(0, height - 1, width, 1);
             let query_text = [
                 Text::Styled(prompt.into(), prompt_style),
-                Text::raw(&state.query),
+                Text::raw(&displayed_query),
             ];
 
             let query_widget = Paragraph::new(query_text.iter());
@@ -538,7 +625,7 @@ pub fn prompt_for_variables<R: std::io::Read, B: tui::backend::Backend + std::io
                 terminal.backend_mut(),
                 "{}",
                 Goto(
-                    prompt.width() as u16 + state.query.width() as u16 + 1,
+                    prompt.width() as u16 + displayed_query.width() as u16 + 1,
                     height
                 )
             )?;
@@ -546,6 +633,7 @@ pub fn prompt_for_variables<R: std::io::Read, B: tui::backend::Backend + std::io
 
         let input = stdin.next();
         if let Some(Ok(key)) = input {
+            state.error = None;
             match key {
                 Key::Ctrl('c') => break,
                 Key::Ctrl('w') => {
@@ -585,36 +673,57 @@ pub fn prompt_for_variables<R: std::io::Read, B: tui::backend::Backend + std::io
                     }
                 }
                 Key::Char('\n') => {
-                    if let Some(index) = state.active_history_item_index {
-                        let answer = KeyValue::new(
-                            names[current_name_index],
-                            &filtered_history_items[index].value,
-                        );
-                        result.push(answer);
-                    } else if !&state.query.is_empty() {
-                        // Assume that an empty string answer is never what they want
-                        let answer = KeyValue::new(names[current_name_index], &state.query);
-
-                        let new_item = HistoryItem {
-                            name: answer.name.clone(),
-                            value: answer.value.clone(),
-                            env_name: env_name.to_string(),
+                    let name = names[current_name_index];
+
+                    // The value to bind, and whether it should be added to the history. Values of
+                    // secret variables never are.
+                    let answer: Option<(&str, bool)> =
+                        if let Some(index) = state.active_history_item_index {
+                            Some((&filtered_history_items[index].value, false))
+                        } else if !&state.query.is_empty() {
+                            Some((&state.query, !secret))
+                        } else {
+                            // Assume that an empty string answer is never what they want, so use
+                            // the default if there is one. Defaults come from the request
+                            // definition, so they aren't added to the history.
+                            info.defaults.get(name).map(|default| (*default, false))
                         };
 
-                        if !full_history.contains(&new_item) {
-                            history_writer.write_record(&[
-                                answer.name.clone(),
-                                answer.value.clone(),
-                                env_name.to_string(),
-                            ])?;
-
-                            // Keep track of the new items so we can re-write the file at the end of
-                            // this function, which is necessary if the number of history items exceeds
-                            // the max_history_items setting in the user's Config
-                            created_items.push(new_item);
-                        }
+                    if let Some((value, add_to_history)) = answer {
+                        // The value was just typed or selected, so it isn't repeated in the error
+                        let expected = match declaration {
+                            Some(declaration) => declaration.expected(value)?,
+                            None => None,
+                        };
 
-                        result.push(answer);
+                        match expected {
+                            Some(expected) => state.error = Some(format!("expected {}", expected)),
+                            None => {
+                                let answer = KeyValue::new(name, value);
+
+                                let new_item = HistoryItem {
+                                    name: answer.name.clone(),
+                                    value: answer.value.clone(),
+                                    env_name: env_name.to_string(),
+                                };
+
+                                if add_to_history && !full_history.contains(&new_item) {
+                                    history_writer.write_record(&[
+                                        answer.name.clone(),
+                                        answer.value.clone(),
+                                        env_name.to_string(),
+                                    ])?;
+
+                                    // Keep track of the new items so we can re-write the file at
+                                    // the end of this function, which is necessary if the number
+                                    // of history items exceeds the max_history_items setting in
+                                    // the user's Config
+                                    created_items.push(new_item);
+                                }
+
+                                result.push(answer);
+                            }
+                        }
                     }
 
                     // If an answer was pushed, the means the current variable is done and we can
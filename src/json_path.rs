@@ -0,0 +1,73 @@
+use serde_json::Value;
+
+/// Look up a value inside a JSON document using a simple path syntax: object keys separated by
+/// dots, and array indices in square brackets, optionally starting with `$`. For example,
+/// `$.data.items[0].id` or `data.items[0].id`. Returns None if any part of the path is missing.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut current = value;
+
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        // A segment is an optional key followed by any number of [index] suffixes, like
+        // `items[0][1]`
+        let (key, mut rest) = match segment.find('[') {
+            Some(i) => (&segment[..i], &segment[i..]),
+            None => (segment, ""),
+        };
+
+        if !key.is_empty() {
+            current = current.get(key)?;
+        }
+
+        while !rest.is_empty() {
+            let close = rest.find(']')?;
+            let index: usize = rest.get(1..close)?.trim().parse().ok()?;
+            current = current.get(index)?;
+            rest = &rest[close + 1..];
+        }
+    }
+
+    Some(current)
+}
+
+/// Render a JSON value as a plain string for use as a variable value. Strings are used as-is
+/// (without quotes), everything else is serialized as JSON.
+pub fn to_plain_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[test]
+fn test_lookup() {
+    let value = serde_json::json!({
+        "data": {
+            "token": "abc",
+            "items": [{ "id": 1 }, { "id": 2, "tags": ["x", "y"] }]
+        },
+        "ok": true
+    });
+
+    assert_eq!(
+        lookup(&value, "$.data.token"),
+        Some(&serde_json::json!("abc"))
+    );
+    assert_eq!(
+        lookup(&value, "data.token"),
+        Some(&serde_json::json!("abc"))
+    );
+    assert_eq!(
+        lookup(&value, "data.items[1].id"),
+        Some(&serde_json::json!(2))
+    );
+    assert_eq!(
+        lookup(&value, "data.items[1].tags[0]"),
+        Some(&serde_json::json!("x"))
+    );
+    assert_eq!(lookup(&value, "ok"), Some(&serde_json::json!(true)));
+    assert_eq!(lookup(&value, "$"), Some(&value));
+    assert_eq!(lookup(&value, "data.missing"), None);
+    assert_eq!(lookup(&value, "data.items[5]"), None);
+    assert_eq!(lookup(&value, "data.items[x]"), None);
+}
@@ -1,9 +1,10 @@
+use crate::templating;
 use serde::Deserialize;
 use std::cmp::Ord;
 use std::fmt::Display;
 use std::str::FromStr;
 
-#[derive(Deserialize, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct KeyValue {
     pub name: String,
     pub value: String,
@@ -16,6 +17,24 @@ impl KeyValue {
             value: value.to_owned(),
         }
     }
+
+    /// A binding for a value that's data rather than something the user wrote, like a value
+    /// extracted from a response or a command's output. Its braces are escaped so it's bound
+    /// exactly as it is instead of being treated as a template.
+    pub fn literal(name: &str, value: &str) -> KeyValue {
+        KeyValue::new(name, &templating::escape(value))
+    }
+}
+
+/// Merge `overrides` into `vars`, which must be sorted by name. Bindings in `overrides` replace any
+/// existing bindings with the same name, and `vars` stays sorted.
+pub fn merge(vars: &mut Vec<KeyValue>, overrides: Vec<KeyValue>) {
+    for binding in overrides {
+        match vars.binary_search_by(|item| item.name.cmp(&binding.name)) {
+            Ok(index) => vars[index] = binding,
+            Err(index) => vars.insert(index, binding),
+        };
+    }
 }
 
 #[derive(Debug)]
@@ -39,3 +58,28 @@ impl FromStr for KeyValue {
         }
     }
 }
+
+#[test]
+fn test_merge() {
+    let mut vars = vec![KeyValue::new("a", "1"), KeyValue::new("c", "3")];
+    merge(
+        &mut vars,
+        vec![KeyValue::new("c", "new"), KeyValue::new("b", "2")],
+    );
+
+    assert_eq!(
+        vars,
+        vec![
+            KeyValue::new("a", "1"),
+            KeyValue::new("b", "2"),
+            KeyValue::new("c", "new")
+        ]
+    );
+}
+
+#[test]
+fn test_literal() {
+    let literal = KeyValue::literal("token", "{\"id\": \"{abc}\"}");
+    assert_eq!(literal.name, "token");
+    assert_eq!(literal.value, "\\{\"id\": \"\\{abc\\}\"\\}");
+}
@@ -1,11 +1,26 @@
 pub mod args;
+pub mod assertions;
+pub mod auth;
 pub mod choice;
 pub mod colors;
+pub mod commands;
 pub mod config;
+pub mod cookies;
+pub mod dotenv;
+pub mod encryption;
 pub mod environment;
+pub mod extract;
 pub mod files;
 pub mod http;
 pub mod interactive;
+pub mod json_path;
 pub mod keyvalue;
+pub mod multipart;
+pub mod oauth2;
+pub mod report;
 pub mod request_definition;
+pub mod runner;
+pub mod secrets;
+pub mod session;
 pub mod templating;
+pub mod workflow;
@@ -0,0 +1,135 @@
+use crate::request_definition::{self, Part};
+use anyhow::Context;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A fully-encoded multipart/form-data body, along with the boundary that separates its parts
+/// (needed for the Content-Type header).
+pub struct MultipartBody {
+    pub boundary: String,
+    pub bytes: Vec<u8>,
+}
+
+impl MultipartBody {
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+}
+
+// Quotes and line breaks can't appear inside the quoted name/filename parameters of the
+// Content-Disposition header, so percent-encode them like browsers do.
+fn escape_param(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn make_boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!(
+        "------------------------rhc{:x}{:x}",
+        nanos,
+        std::process::id()
+    )
+}
+
+/// Encode the provided parts into a multipart/form-data body. File parts are read from disk at this
+/// point, so all variable substitutions must have already been made. Relative file paths are
+/// resolved against the directory containing the request definition at `definition_path`.
+pub fn encode(parts: &[Part], definition_path: &Path) -> anyhow::Result<MultipartBody> {
+    let boundary = make_boundary();
+    let mut bytes: Vec<u8> = Vec::new();
+
+    for part in parts {
+        bytes.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+
+        match part {
+            Part::Text {
+                name,
+                value,
+                content_type,
+            } => {
+                bytes.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"\r\n",
+                        escape_param(name)
+                    )
+                    .as_bytes(),
+                );
+                if let Some(content_type) = content_type {
+                    bytes.extend_from_slice(
+                        format!("Content-Type: {}\r\n", content_type).as_bytes(),
+                    );
+                }
+                bytes.extend_from_slice(b"\r\n");
+                bytes.extend_from_slice(value.as_bytes());
+            }
+            Part::File {
+                name,
+                file,
+                filename,
+                content_type,
+            } => {
+                let path = request_definition::resolve_path(definition_path, file);
+                let contents = std::fs::read(&path)
+                    .with_context(|| format!("Could not read multipart file {}", path.display()))?;
+
+                // If no filename is given, use the last component of the file's path
+                let filename = filename.clone().unwrap_or_else(|| {
+                    path.file_name()
+                        .map(|f| f.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                });
+                let content_type = content_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream");
+
+                bytes.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n",
+                        escape_param(name),
+                        escape_param(&filename)
+                    )
+                    .as_bytes(),
+                );
+                bytes.extend_from_slice(
+                    format!("Content-Type: {}\r\n\r\n", content_type).as_bytes(),
+                );
+                bytes.extend_from_slice(&contents);
+            }
+        }
+
+        bytes.extend_from_slice(b"\r\n");
+    }
+
+    bytes.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    Ok(MultipartBody { boundary, bytes })
+}
+
+#[test]
+fn test_encode() {
+    let parts = vec![
+        Part::Text {
+            name: "first".to_string(),
+            value: "value1".to_string(),
+            content_type: None,
+        },
+        Part::Text {
+            name: "with \"quotes\"".to_string(),
+            value: "{}".to_string(),
+            content_type: Some("application/json".to_string()),
+        },
+    ];
+
+    let body = encode(&parts, Path::new("definition.toml")).unwrap();
+    let expected = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"first\"\r\n\r\nvalue1\r\n--{b}\r\nContent-Disposition: form-data; name=\"with %22quotes%22\"\r\nContent-Type: application/json\r\n\r\n{{}}\r\n--{b}--\r\n",
+        b = body.boundary
+    );
+
+    assert_eq!(String::from_utf8(body.bytes).unwrap(), expected);
+}
@@ -0,0 +1,245 @@
+use crate::config::{self, Config};
+use crate::request_definition::OAuth2;
+use anyhow::{anyhow, Context};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Tokens are refreshed this many seconds before they actually expire, so that they don't expire
+// while the request is in flight
+const EXPIRY_MARGIN_SECONDS: i64 = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CachedToken {
+    pub access_token: String,
+
+    /// Unix timestamp. Tokens without one are used until the cache is deleted.
+    pub expires_at: Option<i64>,
+
+    pub refresh_token: Option<String>,
+}
+
+impl CachedToken {
+    fn is_fresh(&self, now: i64) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| now + EXPIRY_MARGIN_SECONDS < expires_at)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<i64>,
+    refresh_token: Option<String>,
+}
+
+/// Access tokens saved between runs. See `config::state_file_path`.
+pub struct TokenCache {
+    path: PathBuf,
+
+    // Environment name -> cache key -> token
+    tokens: BTreeMap<String, BTreeMap<String, CachedToken>>,
+}
+
+impl TokenCache {
+    pub fn load(config: &Config) -> anyhow::Result<TokenCache> {
+        let path = config::state_file_path(config.token_cache_file.as_deref(), "~/.rhc_tokens");
+
+        let tokens = if path.is_file() {
+            let contents = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&contents).with_context(|| {
+                format!(
+                    "Failed to parse token cache file at {}",
+                    path.to_string_lossy()
+                )
+            })?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(TokenCache { path, tokens })
+    }
+
+    pub fn get(&self, env_name: &str, key: &str) -> Option<&CachedToken> {
+        self.tokens.get(env_name).and_then(|tokens| tokens.get(key))
+    }
+
+    pub fn set(&mut self, env_name: &str, key: &str, token: CachedToken) {
+        self.tokens
+            .entry(env_name.to_string())
+            .or_default()
+            .insert(key.to_string(), token);
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.tokens)?;
+        std::fs::write(&self.path, contents).with_context(|| {
+            format!(
+                "Failed to write token cache file at {}",
+                self.path.to_string_lossy()
+            )
+        })
+    }
+}
+
+// Tokens for different endpoints, clients or scopes are cached separately
+fn cache_key(settings: &OAuth2) -> String {
+    format!(
+        "{} {} {}",
+        settings.token_url,
+        settings.client_id,
+        settings.scopes.join(" ")
+    )
+}
+
+/// The client id and secret are form-urlencoded before they're sent with HTTP Basic (RFC 6749
+/// section 2.3.1), so that one containing a colon can't be mistaken for the end of the other.
+fn form_urlencode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+fn request_token(
+    settings: &OAuth2,
+    config: &Config,
+    grant: &[(&str, &str)],
+) -> anyhow::Result<CachedToken> {
+    let mut form: Vec<(&str, &str)> = grant.to_vec();
+    let scope = settings.scopes.join(" ");
+    if !scope.is_empty() {
+        form.push(("scope", &scope));
+    }
+
+    let mut request_builder = attohttpc::post(&settings.token_url);
+    if let Some(seconds) = config.connect_timeout_seconds {
+        request_builder = request_builder.connect_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(seconds) = config.read_timeout_seconds {
+        request_builder = request_builder.read_timeout(Duration::from_secs(seconds));
+    }
+
+    // Confidential clients authenticate with HTTP Basic; public clients just identify themselves
+    match &settings.client_secret {
+        Some(secret) => {
+            request_builder = request_builder.basic_auth(
+                form_urlencode(&settings.client_id),
+                Some(form_urlencode(secret)),
+            )
+        }
+        None => form.push(("client_id", &settings.client_id)),
+    }
+
+    let res = request_builder.form(&form)?.send()?;
+    let status = res.status();
+    let body = res.text()?;
+    if !status.is_success() {
+        return Err(anyhow!(
+            "The token endpoint {} responded with {}: {}",
+            settings.token_url,
+            status,
+            body
+        ));
+    }
+
+    let response: TokenResponse =
+        serde_json::from_str(&body).context("Could not parse the token endpoint's response")?;
+    Ok(CachedToken {
+        access_token: response.access_token,
+        expires_at: response
+            .expires_in
+            .map(|seconds| Utc::now().timestamp() + seconds),
+        refresh_token: response.refresh_token,
+    })
+}
+
+/// Get an access token for the given settings, from the token cache if there's an unexpired one
+/// for this environment, or else from the token endpoint.
+pub fn access_token(settings: &OAuth2, config: &Config, env_name: &str) -> anyhow::Result<String> {
+    let mut cache = TokenCache::load(config)?;
+    let key = cache_key(settings);
+
+    let cached = cache.get(env_name, &key).cloned();
+    if let Some(token) = &cached {
+        if token.is_fresh(Utc::now().timestamp()) {
+            return Ok(token.access_token.clone());
+        }
+    }
+
+    // An expired token's refresh token is tried first. If the server rejects it, start over with
+    // the grant from the settings.
+    let cached_refresh_token = cached.and_then(|token| token.refresh_token);
+    let refreshed = cached_refresh_token.as_ref().and_then(|refresh_token| {
+        request_token(
+            settings,
+            config,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ],
+        )
+        .ok()
+    });
+
+    let mut token = match (refreshed, &settings.refresh_token) {
+        (Some(token), _) => token,
+        (None, Some(refresh_token)) => request_token(
+            settings,
+            config,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ],
+        )?,
+        (None, None) => request_token(settings, config, &[("grant_type", "client_credentials")])?,
+    };
+
+    // Servers don't always send a new refresh token when refreshing, in which case the old one
+    // can be reused
+    if token.refresh_token.is_none() {
+        token.refresh_token = cached_refresh_token;
+    }
+
+    let access_token = token.access_token.clone();
+    cache.set(env_name, &key, token);
+    cache.save()?;
+
+    Ok(access_token)
+}
+
+#[test]
+fn test_token_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        token_cache_file: Some(dir.path().join("tokens").to_string_lossy().into_owned()),
+        ..Config::default()
+    };
+    let token = CachedToken {
+        access_token: "abc".to_string(),
+        expires_at: Some(1000),
+        refresh_token: None,
+    };
+
+    let mut cache = TokenCache::load(&config).unwrap();
+    cache.set("staging", "key", token.clone());
+    cache.save().unwrap();
+
+    let cache = TokenCache::load(&config).unwrap();
+    assert_eq!(cache.get("staging", "key"), Some(&token));
+    assert_eq!(cache.get("prod", "key"), None);
+
+    assert!(token.is_fresh(900));
+    assert!(!token.is_fresh(980));
+    assert!(!token.is_fresh(1000));
+    assert!(CachedToken {
+        expires_at: None,
+        ..token
+    }
+    .is_fresh(1_000_000));
+}
+
+#[test]
+fn test_form_urlencode() {
+    assert_eq!(form_urlencode("my-client_1.0"), "my-client_1.0");
+    assert_eq!(form_urlencode("a:b%c d/ü"), "a%3Ab%25c+d%2F%C3%BC");
+}
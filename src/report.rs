@@ -0,0 +1,201 @@
+use crate::runner::TestResult;
+use serde_json::json;
+use std::time::Duration;
+
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64())
+}
+
+/// Render test results as a JUnit XML report, with one testcase per request definition. The
+/// classname of each testcase is the directory that the definition is in (with dots instead of
+/// slashes), like a Java package.
+pub fn junit(results: &[TestResult]) -> String {
+    let failures = results
+        .iter()
+        .filter(|r| r.error.is_none() && !r.failures.is_empty())
+        .count();
+    let errors = results.iter().filter(|r| r.error.is_some()).count();
+    let total_time: Duration = results.iter().map(|r| r.elapsed).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"rhc\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">\n",
+        results.len(),
+        failures,
+        errors,
+        seconds(total_time)
+    ));
+    xml.push_str(&format!(
+        "  <testsuite name=\"rhc\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">\n",
+        results.len(),
+        failures,
+        errors,
+        seconds(total_time)
+    ));
+
+    for result in results {
+        let classname = match result.name.rfind('/') {
+            Some(i) => result.name[..i].replace('/', "."),
+            None => "rhc".to_string(),
+        };
+
+        xml.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\"",
+            escape_xml(&result.name),
+            escape_xml(&classname),
+            seconds(result.elapsed)
+        ));
+
+        if let Some(error) = &result.error {
+            xml.push_str(&format!(
+                ">\n      <error message=\"{}\">{}</error>\n    </testcase>\n",
+                escape_xml(error),
+                escape_xml(error)
+            ));
+        } else if !result.failures.is_empty() {
+            xml.push_str(&format!(
+                ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                escape_xml(&result.failures[0]),
+                escape_xml(&result.failures.join("\n"))
+            ));
+        } else {
+            xml.push_str("/>\n");
+        }
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Render test results as a JSON report, including the request that was sent and the status of
+/// the response for each request definition.
+pub fn json(results: &[TestResult]) -> serde_json::Value {
+    let passed = results.iter().filter(|r| r.passed()).count();
+
+    let results: Vec<serde_json::Value> = results
+        .iter()
+        .map(|r| {
+            json!({
+                "name": r.name,
+                "passed": r.passed(),
+                "duration_ms": r.elapsed.as_millis() as u64,
+                "request": {
+                    "method": r.method,
+                    "url": r.url,
+                },
+                "response": {
+                    "status": r.status,
+                },
+                "error": r.error,
+                "failures": r.failures,
+            })
+        })
+        .collect();
+
+    json!({
+        "tests": results.len(),
+        "passed": passed,
+        "failed": results.len() - passed,
+        "results": results,
+    })
+}
+
+#[cfg(test)]
+fn sample_results() -> Vec<TestResult> {
+    vec![
+        TestResult {
+            name: "get.toml".to_string(),
+            elapsed: Duration::from_millis(1500),
+            method: Some("GET".to_string()),
+            url: Some("http://localhost/a?b=1&c=2".to_string()),
+            status: Some(200),
+            error: None,
+            failures: vec![],
+            extracted: vec![],
+        },
+        TestResult {
+            name: "users/create.toml".to_string(),
+            elapsed: Duration::from_millis(20),
+            method: Some("POST".to_string()),
+            url: Some("http://localhost/users".to_string()),
+            status: Some(400),
+            error: None,
+            failures: vec![
+                "Expected status 201, got 400".to_string(),
+                "Expected the body to match <id>".to_string(),
+            ],
+            extracted: vec![],
+        },
+        TestResult {
+            name: "broken.toml".to_string(),
+            elapsed: Duration::from_millis(1),
+            method: None,
+            url: None,
+            status: None,
+            error: Some("Unbound variables: token".to_string()),
+            failures: vec![],
+            extracted: vec![],
+        },
+    ]
+}
+
+#[test]
+fn test_junit() {
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="rhc" tests="3" failures="1" errors="1" time="1.521">
+  <testsuite name="rhc" tests="3" failures="1" errors="1" time="1.521">
+    <testcase name="get.toml" classname="rhc" time="1.500"/>
+    <testcase name="users/create.toml" classname="users" time="0.020">
+      <failure message="Expected status 201, got 400">Expected status 201, got 400
+Expected the body to match &lt;id&gt;</failure>
+    </testcase>
+    <testcase name="broken.toml" classname="rhc" time="0.001">
+      <error message="Unbound variables: token">Unbound variables: token</error>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#;
+
+    assert_eq!(junit(&sample_results()), expected);
+}
+
+#[test]
+fn test_json() {
+    let report = json(&sample_results());
+
+    assert_eq!(report["tests"], 3);
+    assert_eq!(report["passed"], 1);
+    assert_eq!(report["failed"], 2);
+    assert_eq!(
+        report["results"][0],
+        json!({
+            "name": "get.toml",
+            "passed": true,
+            "duration_ms": 1500,
+            "request": { "method": "GET", "url": "http://localhost/a?b=1&c=2" },
+            "response": { "status": 200 },
+            "error": null,
+            "failures": [],
+        })
+    );
+    assert_eq!(
+        report["results"][1]["failures"][1],
+        "Expected the body to match <id>"
+    );
+    assert_eq!(report["results"][2]["response"]["status"], json!(null));
+}
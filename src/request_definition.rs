@@ -1,14 +1,20 @@
 use crate::keyvalue::KeyValue;
-use serde::Deserialize;
+use anyhow::{anyhow, Context};
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::fs;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Metadata {
     pub description: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub enum Method {
     GET,
     POST,
@@ -35,48 +41,472 @@ impl Method {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Request {
     pub url: String,
     pub method: Method,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Query {
     pub params: Vec<KeyValue>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 #[serde(tag = "type", content = "content")]
 pub enum Content {
     Text(String),
     Json(String),
+    /// A JSON body that's parsed when the request definition is loaded. Variables in its strings
+    /// are substituted after parsing, and a string that's just a typed variable like
+    /// `"{count:number}"` becomes a JSON value of that type.
+    #[serde(rename = "typed_json")]
+    TypedJson(#[serde(deserialize_with = "parse_json")] serde_json::Value),
     UrlEncoded(Vec<KeyValue>),
+    Multipart(Vec<Part>),
+    File(FileBody),
 }
 
-#[derive(Deserialize, Debug)]
+fn parse_json<'de, D>(deserializer: D) -> Result<serde_json::Value, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let contents = String::deserialize(deserializer)?;
+    serde_json::from_str(&contents).map_err(serde::de::Error::custom)
+}
+
+/// A body whose contents are read from a file, or from stdin if the path is "-". Relative paths
+/// are resolved against the directory containing the request definition file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FileBody {
+    pub path: String,
+
+    /// If true, the file is read as UTF-8 text and variables in it are substituted like in any
+    /// other body. Otherwise the bytes are sent untouched.
+    #[serde(default)]
+    pub template: bool,
+
+    pub content_type: Option<String>,
+
+    /// Populated by `RequestDefinition::load_body_file`.
+    #[serde(skip)]
+    pub contents: Option<FileContents>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FileContents {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+/// A single part of a multipart/form-data body. A part is either a plain text field, or the
+/// contents of a file on disk.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Part {
+    File {
+        name: String,
+        file: String,
+        filename: Option<String>,
+        content_type: Option<String>,
+    },
+    Text {
+        name: String,
+        value: String,
+        content_type: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Headers {
     pub headers: Vec<KeyValue>,
 }
 
-#[derive(Deserialize, Debug)]
+/// Credentials to send with the request, so that the Authorization header doesn't have to be built
+/// by hand
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+#[serde(tag = "type")]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer {
+        token: String,
+    },
+
+    /// HTTP Digest authentication. The request is first sent without credentials, and then again
+    /// with a response to the challenge the server sends back.
+    Digest {
+        username: String,
+        password: String,
+    },
+
+    /// Fetch an access token from an OAuth2 token endpoint, and send it as a bearer token
+    #[serde(rename = "oauth2")]
+    OAuth2(OAuth2),
+
+    /// Sign the request with AWS Signature Version 4
+    #[serde(rename = "aws_sigv4")]
+    AwsSigV4(AwsSigV4),
+
+    /// Sign the request with an HMAC over a configurable canonical string
+    Hmac(HmacSignature),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OAuth2 {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+
+    #[serde(default)]
+    pub scopes: Vec<String>,
+
+    /// If present, use the refresh token grant instead of client credentials
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AwsSigV4 {
+    pub region: String,
+    pub service: String,
+    pub access_key: String,
+    pub secret_key: String,
+
+    /// Only needed for temporary credentials
+    pub session_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HmacSignature {
+    pub key: String,
+
+    #[serde(default)]
+    pub algorithm: HmacAlgorithm,
+
+    /// The parts of the request that make up the string to sign, in order
+    pub canonical: Vec<CanonicalPart>,
+
+    /// Put between each part of the canonical string
+    #[serde(default = "default_separator")]
+    pub separator: String,
+
+    /// The header to send the signature in
+    pub header: String,
+
+    #[serde(default)]
+    pub encoding: SignatureEncoding,
+
+    /// A header to send the timestamp used in the signature in, if any
+    pub timestamp_header: Option<String>,
+
+    #[serde(default)]
+    pub timestamp_format: TimestampFormat,
+}
+
+fn default_separator() -> String {
+    "\n".to_string()
+}
+
+/// One part of an HMAC canonical string. Written like `"path_and_query"`, or `"header:NAME"` for
+/// the value of a request header.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub enum CanonicalPart {
+    Method,
+    Url,
+    Host,
+    Path,
+    Query,
+    PathAndQuery,
+    Timestamp,
+    Body,
+    BodySha256,
+    Header(String),
+}
+
+impl TryFrom<String> for CanonicalPart {
+    type Error = String;
+
+    fn try_from(part: String) -> Result<Self, Self::Error> {
+        match part.as_str() {
+            "method" => Ok(CanonicalPart::Method),
+            "url" => Ok(CanonicalPart::Url),
+            "host" => Ok(CanonicalPart::Host),
+            "path" => Ok(CanonicalPart::Path),
+            "query" => Ok(CanonicalPart::Query),
+            "path_and_query" => Ok(CanonicalPart::PathAndQuery),
+            "timestamp" => Ok(CanonicalPart::Timestamp),
+            "body" => Ok(CanonicalPart::Body),
+            "body_sha256" => Ok(CanonicalPart::BodySha256),
+            _ => match part.strip_prefix("header:") {
+                Some(name) => Ok(CanonicalPart::Header(name.to_string())),
+                None => Err(format!("Unknown canonical string part: {}", part)),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// Seconds since the Unix epoch
+    #[default]
+    Unix,
+
+    /// Milliseconds since the Unix epoch
+    UnixMs,
+
+    /// Like 2020-05-01T12:30:00Z
+    Iso8601,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Extract {
+    pub values: Vec<Extraction>,
+}
+
+/// A value to pull out of the response and save under `name`, so it can be bound as a variable in
+/// later requests.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Extraction {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: ExtractionSource,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+#[serde(tag = "type")]
+pub enum ExtractionSource {
+    Json {
+        path: String,
+    },
+    Header {
+        header: String,
+    },
+    Status,
+    Regex {
+        pattern: String,
+        group: Option<usize>,
+    },
+}
+
+/// Checks to run against the response when running `rhc test`
+#[derive(Deserialize, Debug, Clone)]
+pub struct Assertions {
+    pub status: Option<StatusAssertion>,
+    #[serde(default)]
+    pub headers: Vec<HeaderAssertion>,
+    #[serde(default)]
+    pub json: Vec<JsonAssertion>,
+    pub body_matches: Option<String>,
+    pub max_response_time_ms: Option<u64>,
+}
+
+/// Either an exact status code (`status = 200`), a class of status codes (`status = "2xx"`), or an
+/// inclusive range (`status = { min = 200, max = 299 }`).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum StatusAssertion {
+    Exact(u16),
+    Class(String),
+    Range { min: u16, max: u16 },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HeaderAssertion {
+    pub name: String,
+    pub equals: Option<String>,
+    pub contains: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct JsonAssertion {
+    pub path: String,
+    pub equals: Option<serde_json::Value>,
+    pub exists: Option<bool>,
+}
+
+/// Information about a variable that the request definition uses: a description to show when
+/// prompting for it, and rules that its value has to follow.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct VariableDeclaration {
+    pub description: Option<String>,
+
+    #[serde(rename = "type", default)]
+    pub value_type: DeclaredType,
+
+    /// A regex that the whole value has to match
+    pub pattern: Option<String>,
+
+    /// The only values that are allowed, which are also offered when prompting
+    pub values: Option<Vec<String>>,
+
+    /// Whether the value should be kept out of the history file, the prompt and verbose output
+    #[serde(default)]
+    pub secret: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DeclaredType {
+    #[default]
+    String,
+    Int,
+    Bool,
+    Uuid,
+}
+
+impl VariableDeclaration {
+    /// Check a value against the declared type, allowed values and pattern. The error only
+    /// includes the value if the variable isn't secret.
+    pub fn validate(&self, value: &str) -> anyhow::Result<()> {
+        match self.expected(value)? {
+            Some(expected) if self.secret => Err(anyhow!("Expected {}", expected)),
+            Some(expected) => Err(anyhow!("Expected {}, but got '{}'", expected, value)),
+            None => Ok(()),
+        }
+    }
+
+    /// A description of what the value should be, if it isn't valid
+    pub fn expected(&self, value: &str) -> anyhow::Result<Option<String>> {
+        let valid_type = match self.value_type {
+            DeclaredType::String => true,
+            DeclaredType::Int => value.parse::<i64>().is_ok(),
+            DeclaredType::Bool => value == "true" || value == "false",
+            DeclaredType::Uuid => Uuid::parse_str(value).is_ok(),
+        };
+        if !valid_type {
+            let expected = match self.value_type {
+                DeclaredType::String => "a string",
+                DeclaredType::Int => "an integer",
+                DeclaredType::Bool => "true or false",
+                DeclaredType::Uuid => "a UUID",
+            };
+            return Ok(Some(expected.to_string()));
+        }
+
+        if let Some(values) = &self.values {
+            if !values.iter().any(|allowed| allowed == value) {
+                return Ok(Some(format!("one of {}", values.join(", "))));
+            }
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let regex = Regex::new(&format!("^(?:{})$", pattern))?;
+            if !regex.is_match(value) {
+                return Ok(Some(format!("a value matching {}", pattern)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct RequestDefinition {
     pub metadata: Option<Metadata>,
     pub request: Request,
     pub query: Option<Query>,
     pub body: Option<Content>,
     pub headers: Option<Headers>,
+    pub auth: Option<Auth>,
+    pub extract: Option<Extract>,
+    pub assertions: Option<Assertions>,
+
+    #[serde(default)]
+    pub variables: BTreeMap<String, VariableDeclaration>,
+
+    /// The file this definition was loaded from
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// Resolve a file path used in a request definition, with `~` expanded and relative paths resolved
+/// against the directory containing the definition file.
+pub fn resolve_path(definition_path: &Path, path: &str) -> PathBuf {
+    let expanded = shellexpand::tilde(path);
+    let full_path = PathBuf::from(expanded.as_ref());
+    match definition_path.parent() {
+        Some(parent) if full_path.is_relative() => parent.join(full_path),
+        _ => full_path,
+    }
 }
 
 impl RequestDefinition {
     pub fn new(path: &Path) -> anyhow::Result<RequestDefinition> {
         let contents = fs::read_to_string(path)?;
 
-        let request_def = toml::from_str(&contents)?;
+        let mut request_def: RequestDefinition = toml::from_str(&contents)?;
+        request_def.path = path.to_path_buf();
+
+        for (name, declaration) in &request_def.variables {
+            if let Some(pattern) = &declaration.pattern {
+                Regex::new(pattern)
+                    .with_context(|| format!("Invalid pattern for the variable {}", name))?;
+            }
+        }
 
         Ok(request_def)
     }
+
+    /// If the body is read from a file, read it into memory. This is done separately from `new` so
+    /// that files (or stdin) are only read for the definition that's actually being sent, and must
+    /// happen before variables are substituted so that templated files can have their variables
+    /// bound. Calling it more than once has no effect.
+    pub fn load_body_file(&mut self) -> anyhow::Result<()> {
+        if let Some(Content::File(file_body)) = &mut self.body {
+            if file_body.contents.is_some() {
+                return Ok(());
+            }
+
+            let bytes: Vec<u8> = if file_body.path == "-" {
+                let mut buffer = Vec::new();
+                std::io::stdin()
+                    .read_to_end(&mut buffer)
+                    .context("Could not read request body from stdin")?;
+                buffer
+            } else {
+                let full_path = resolve_path(&self.path, &file_body.path);
+                fs::read(&full_path).with_context(|| {
+                    format!("Could not read request body file {}", full_path.display())
+                })?
+            };
+
+            let contents = if file_body.template {
+                FileContents::Text(
+                    String::from_utf8(bytes)
+                        .context("Templated body files must contain valid UTF-8")?,
+                )
+            } else {
+                FileContents::Bytes(bytes)
+            };
+
+            file_body.contents = Some(contents);
+        }
+
+        Ok(())
+    }
 }
 
 #[test]
@@ -108,3 +538,54 @@ fn test_bad_files() {
         );
     }
 }
+
+#[test]
+fn test_variable_declarations() {
+    let def: RequestDefinition =
+        RequestDefinition::new(Path::new("test_definitions/ok/declared_variables.toml")).unwrap();
+
+    let stage = &def.variables["stage"];
+    assert_eq!(
+        stage.description.as_deref(),
+        Some("Which deployment to call")
+    );
+    assert!(stage.validate("staging").is_ok());
+    assert_eq!(
+        stage.validate("qa").unwrap_err().to_string(),
+        "Expected one of dev, staging, prod, but got 'qa'"
+    );
+
+    let user_id = &def.variables["user_id"];
+    assert!(user_id
+        .validate("67e55044-10b1-426f-9247-bb680e5fe0c8")
+        .is_ok());
+    assert!(user_id.validate("123").is_err());
+
+    // The pattern has to match the whole value
+    let limit = &def.variables["limit"];
+    assert!(limit.validate("50").is_ok());
+    assert!(limit.validate("500").is_err());
+    assert!(limit.validate("0").is_err());
+
+    let flag = VariableDeclaration {
+        value_type: DeclaredType::Bool,
+        ..VariableDeclaration::default()
+    };
+    assert!(flag.validate("false").is_ok());
+    assert_eq!(
+        flag.validate("no").unwrap_err().to_string(),
+        "Expected true or false, but got 'no'"
+    );
+
+    // The values of secret variables aren't included in errors
+    let pin = VariableDeclaration {
+        value_type: DeclaredType::Int,
+        secret: true,
+        ..VariableDeclaration::default()
+    };
+    assert_eq!(
+        pin.validate("abcd").unwrap_err().to_string(),
+        "Expected an integer"
+    );
+    assert_eq!(pin.expected("abcd").unwrap().as_deref(), Some("an integer"));
+}
@@ -0,0 +1,132 @@
+use crate::assertions;
+use crate::commands::CommandVariables;
+use crate::extract;
+use crate::files::load_file;
+use crate::http;
+use crate::http::{CapturedResponse, RequestContext};
+use crate::keyvalue;
+use crate::keyvalue::KeyValue;
+use crate::request_definition::RequestDefinition;
+use crate::templating;
+use anyhow::{anyhow, Context};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The outcome of sending a single request definition in `rhc test`
+pub struct TestResult {
+    /// The definition's path, relative to the directory being tested
+    pub name: String,
+    pub elapsed: Duration,
+
+    // These are filled in as far as the test got before an error, if any
+    pub method: Option<String>,
+    pub url: Option<String>,
+    pub status: Option<u16>,
+
+    /// Set if the request couldn't be sent or its response couldn't be read
+    pub error: Option<String>,
+
+    /// Descriptions of the assertions that failed
+    pub failures: Vec<String>,
+
+    /// Values pulled out of the response by the definition's `extract` table
+    pub extracted: Vec<KeyValue>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.failures.is_empty()
+    }
+}
+
+/// Bind the provided variables in a request definition without any user interaction, running
+/// commands for any command variables that are needed, falling back to default values and failing
+/// if any variables are left unbound. `vars` must be sorted by name.
+pub fn bind_variables(
+    def: &mut RequestDefinition,
+    vars: &[KeyValue],
+    dotenv: &[KeyValue],
+    commands: &mut CommandVariables,
+) -> anyhow::Result<()> {
+    def.load_body_file()?;
+
+    let mut vars = vars.to_vec();
+    let command_vars = commands.resolve(&templating::list_unbound_variables(def, &vars, true))?;
+    keyvalue::merge(&mut vars, command_vars);
+
+    let unbound = templating::list_unbound_variables(def, &vars, false);
+    if !unbound.is_empty() {
+        return Err(anyhow!("Unbound variables: {}", unbound.join(", ")));
+    }
+
+    templating::substitute_all(def, &vars, dotenv)
+}
+
+fn send_and_check(
+    path: &Path,
+    vars: &[KeyValue],
+    context: &mut RequestContext,
+    result: &mut TestResult,
+) -> anyhow::Result<()> {
+    let mut def = load_file(path, RequestDefinition::new, "request definition")?;
+    if def.auth.is_none() {
+        def.auth = context.default_auth.clone();
+    }
+    bind_variables(&mut def, vars, &context.dotenv, &mut context.commands)?;
+
+    result.method = Some(def.request.method.to_http_method().to_string());
+    result.url = Some(def.request.url.clone());
+
+    let assertions = def.assertions.take();
+    let extractions = def.extract.take();
+
+    let start = Instant::now();
+    let res = http::send_request(def, context).context("Failed sending request")?;
+    result.status = Some(res.status().as_u16());
+    let res = CapturedResponse::new(res).context("Failed reading response")?;
+    let elapsed = start.elapsed();
+
+    if let Some(assertions) = assertions {
+        result.failures = assertions::check(&assertions, &res, elapsed);
+    }
+
+    if let Some(extractions) = extractions {
+        match extract::extract_values(&extractions.values, &res) {
+            Ok(extracted) => result.extracted = extracted,
+            Err(e) => result
+                .failures
+                .push(format!("Failed extracting values from the response: {}", e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Send the request definition at `path` with `vars` bound, check the response against its
+/// assertions, and extract any values it specifies. A definition without assertions passes as long
+/// as a response is received.
+pub fn run_test(
+    path: &Path,
+    name: &str,
+    vars: &[KeyValue],
+    context: &mut RequestContext,
+) -> TestResult {
+    let mut result = TestResult {
+        name: name.to_string(),
+        elapsed: Duration::default(),
+        method: None,
+        url: None,
+        status: None,
+        error: None,
+        failures: vec![],
+        extracted: vec![],
+    };
+
+    let start = Instant::now();
+    if let Err(e) = send_and_check(path, vars, context, &mut result) {
+        result.error = Some(format!("{:#}", e));
+    }
+    result.elapsed = start.elapsed();
+
+    result
+}
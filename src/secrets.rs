@@ -0,0 +1,92 @@
+use crate::config::Config;
+use crate::request_definition::RequestDefinition;
+use anyhow::Context;
+use regex::Regex;
+use std::collections::HashSet;
+
+/// Shown instead of the value of a secret variable
+pub const REDACTED: &str = "********";
+
+/// Which variables are secret, so that their values are masked when prompting for them, never
+/// saved to the history file, and redacted in verbose output. A variable is secret if the
+/// environment lists it in `secrets`, if its declaration in the request definition has `secret =
+/// true`, or if its name matches one of the `secret_patterns` regexes in the config file.
+pub struct Secrets {
+    names: HashSet<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Secrets {
+    pub fn new(
+        config: &Config,
+        env_secrets: &[String],
+        def: &RequestDefinition,
+    ) -> anyhow::Result<Secrets> {
+        let patterns = config
+            .secret_patterns
+            .iter()
+            .flatten()
+            .map(|pattern| {
+                Regex::new(pattern).with_context(|| format!("Invalid secret pattern {}", pattern))
+            })
+            .collect::<anyhow::Result<Vec<Regex>>>()?;
+
+        let declared = def
+            .variables
+            .iter()
+            .filter(|(_, declaration)| declaration.secret)
+            .map(|(name, _)| name);
+        let names = env_secrets.iter().chain(declared).cloned().collect();
+
+        Ok(Secrets { names, patterns })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name) || self.patterns.iter().any(|pattern| pattern.is_match(name))
+    }
+
+    /// The value, or a placeholder if the variable is secret
+    pub fn redact<'a>(&self, name: &str, value: &'a str) -> &'a str {
+        if self.contains(name) {
+            REDACTED
+        } else {
+            value
+        }
+    }
+}
+
+#[test]
+fn test_secrets() {
+    let config = Config {
+        secret_patterns: Some(vec!["(?i)password".to_string()]),
+        ..Config::default()
+    };
+    let def: RequestDefinition = toml::from_str(
+        r#"
+        [request]
+        url = "http://localhost/{token}"
+        method = "GET"
+
+        [variables.token]
+        secret = true
+
+        [variables.user]
+        description = "Not secret"
+        "#,
+    )
+    .unwrap();
+
+    let secrets = Secrets::new(&config, &["api_key".to_string()], &def).unwrap();
+    assert!(secrets.contains("token"));
+    assert!(secrets.contains("api_key"));
+    assert!(secrets.contains("DB_PASSWORD"));
+    assert!(!secrets.contains("user"));
+    assert_eq!(secrets.redact("token", "abc"), REDACTED);
+    assert_eq!(secrets.redact("user", "abc"), "abc");
+
+    let config = Config {
+        secret_patterns: Some(vec!["[".to_string()]),
+        ..Config::default()
+    };
+    assert!(Secrets::new(&config, &[], &def).is_err());
+}
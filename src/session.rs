@@ -0,0 +1,94 @@
+use crate::config::{self, Config};
+use crate::keyvalue::KeyValue;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Values extracted from responses, persisted between runs so they can be bound like environment
+/// variables. See `config::state_file_path`.
+#[derive(Debug, Default)]
+pub struct Session {
+    path: PathBuf,
+
+    // Environment name -> variable name -> value
+    values: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Session {
+    /// Load the session file specified in the Config, or start an empty session if it doesn't exist
+    /// yet.
+    pub fn load(config: &Config) -> anyhow::Result<Session> {
+        let path = config::state_file_path(config.session_file.as_deref(), "~/.rhc_session");
+
+        let values = if path.is_file() {
+            let contents = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&contents).with_context(|| {
+                format!("Failed to parse session file at {}", path.to_string_lossy())
+            })?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Session { path, values })
+    }
+
+    /// The values that were extracted while using the given environment
+    pub fn variables(&self, env_name: &str) -> Vec<KeyValue> {
+        self.values
+            .get(env_name)
+            .map(|vars| {
+                vars.iter()
+                    .map(|(name, value)| KeyValue::new(name, value))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Add or overwrite values for the given environment. Call `save` to persist them.
+    pub fn set(&mut self, env_name: &str, vars: &[KeyValue]) {
+        let env_values = self.values.entry(env_name.to_string()).or_default();
+        for var in vars {
+            env_values.insert(var.name.clone(), var.value.clone());
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.values)?;
+        std::fs::write(&self.path, contents).with_context(|| {
+            format!(
+                "Failed to write session file at {}",
+                self.path.to_string_lossy()
+            )
+        })
+    }
+}
+
+#[test]
+fn test_session_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        session_file: Some(dir.path().join("session").to_string_lossy().into_owned()),
+        ..Config::default()
+    };
+
+    let mut session = Session::load(&config).unwrap();
+    assert!(session.variables("staging").is_empty());
+
+    session.set("staging", &[KeyValue::new("token", "first")]);
+    session.set(
+        "staging",
+        &[KeyValue::new("token", "second"), KeyValue::new("id", "1")],
+    );
+    session.set("prod", &[KeyValue::new("token", "other")]);
+    session.save().unwrap();
+
+    let session = Session::load(&config).unwrap();
+    assert_eq!(
+        session.variables("staging"),
+        vec![KeyValue::new("id", "1"), KeyValue::new("token", "second")]
+    );
+    assert_eq!(
+        session.variables("prod"),
+        vec![KeyValue::new("token", "other")]
+    );
+}
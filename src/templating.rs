@@ -1,154 +1,723 @@
+use crate::auth;
 use crate::keyvalue::KeyValue;
-use crate::request_definition::{Content, RequestDefinition};
+use crate::request_definition::{Auth, Content, FileContents, Part, RequestDefinition};
+use anyhow::{anyhow, Context};
+use base64::Engine;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{SecondsFormat, Utc};
 use lazy_static::lazy_static;
+use rand::Rng;
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
 
 lazy_static! {
-    static ref RE: Regex = Regex::new(r"\{(.+?)\}").unwrap();
+    // Either an escaped brace, or a variable. Names are identifiers (plus `$`, `.` and `-` for
+    // built-ins), so braces around anything else, like an inline JSON object, are left alone.
+    static ref RE: Regex =
+        Regex::new(r"\\([{}])|\{([A-Za-z_$][A-Za-z0-9_.$-]*(?:[:|][^{}\n\\]*)?)\}").unwrap();
 }
 
-// Naive substitution, just replace each variable one-by-one.
-// Could optimize at some point, but possibly not worth it.
-pub fn substitute<'a>(base: &'a str, variables: &'a [KeyValue]) -> (Cow<'a, str>, bool) {
-    let mut output: String = base.to_owned();
-    for var in variables {
-        let target = format!("{{{}}}", var.name);
-        output = output.replace(&target, &var.value);
+/// A variable in a template: `{name}`, or `{name:default}` for one that falls back to a default
+/// value when it isn't bound. A type can come before the default, like `{count:number}` or
+/// `{count:number:1}`. Any of these can be followed by filters that transform the value, like
+/// `{name|urlencode}`.
+#[derive(Debug, PartialEq)]
+struct Placeholder<'a> {
+    name: &'a str,
+    value_type: Option<ValueType>,
+    default: Option<&'a str>,
+    filters: Vec<&'a str>,
+}
+
+impl<'a> Placeholder<'a> {
+    fn parse(inner: &'a str) -> Placeholder<'a> {
+        let mut parts = inner.split('|');
+        let head = parts.next().unwrap_or("");
+        let filters: Vec<&str> = parts.collect();
+
+        let (name, rest) = match head.split_once(':') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (head, None),
+        };
+
+        let (value_type, default) = match rest {
+            Some(rest) => {
+                let (first, after) = match rest.split_once(':') {
+                    Some((first, after)) => (first, Some(after)),
+                    None => (rest, None),
+                };
+                match ValueType::parse(first) {
+                    Some(value_type) => (Some(value_type), after),
+                    None => (None, Some(rest)),
+                }
+            }
+            None => (None, None),
+        };
+
+        Placeholder {
+            name,
+            value_type,
+            default,
+            filters,
+        }
+    }
+}
+
+/// The type of a variable's value. Values are always checked against their type, and in a typed
+/// JSON body a string that's just one typed variable becomes a JSON value of that type.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ValueType {
+    Number,
+    Bool,
+}
+
+impl ValueType {
+    fn parse(s: &str) -> Option<ValueType> {
+        match s {
+            "number" => Some(ValueType::Number),
+            "bool" => Some(ValueType::Bool),
+            _ => None,
+        }
+    }
+
+    // The error leaves out the value, since it could be a secret
+    fn to_json(self, value: &str) -> anyhow::Result<serde_json::Value> {
+        match self {
+            ValueType::Number => value
+                .parse::<serde_json::Number>()
+                .map(serde_json::Value::Number)
+                .map_err(|_| anyhow!("Expected a number")),
+            ValueType::Bool => match value {
+                "true" => Ok(serde_json::Value::Bool(true)),
+                "false" => Ok(serde_json::Value::Bool(false)),
+                _ => Err(anyhow!("Expected true or false")),
+            },
+        }
+    }
+}
+
+fn apply_filter(filter: &str, value: String) -> anyhow::Result<String> {
+    match filter {
+        "urlencode" => Ok(auth::uri_encode(value.as_bytes())),
+        "base64" => Ok(base64::engine::general_purpose::STANDARD.encode(value)),
+        // Escaped for use inside a JSON string, so without the surrounding quotes
+        "json" => {
+            let encoded = serde_json::to_string(&value)?;
+            Ok(encoded[1..encoded.len() - 1].to_string())
+        }
+        "upper" => Ok(value.to_uppercase()),
+        "lower" => Ok(value.to_lowercase()),
+        "sha256" => Ok(auth::hex(&Sha256::digest(value.as_bytes()))),
+        _ => Err(anyhow!("Unknown filter: {}", filter)),
+    }
+}
+
+// Built-in variables start with a $, and are generated fresh every time they're used. Anything
+// after a colon (and any type) is arguments rather than a default value.
+fn builtin(name: &str, args: Option<&str>, dotenv: &HashMap<&str, &str>) -> anyhow::Result<String> {
+    // Environment variables from the dotenv file, then the process, which can have a default value
+    // like ordinary variables
+    if let Some(env_name) = name.strip_prefix("$env.") {
+        if let Some(value) = dotenv.get(env_name) {
+            return Ok(value.to_string());
+        }
+
+        return match (std::env::var(env_name), args) {
+            (Ok(value), _) => Ok(value),
+            (Err(_), Some(default)) => Ok(default.to_string()),
+            (Err(e), None) => Err(anyhow!("Environment variable {}: {}", env_name, e)),
+        };
+    }
+
+    match (name, args) {
+        ("$uuid", None) => Ok(Uuid::new_v4().to_string()),
+        ("$timestamp", None) => Ok(Utc::now().timestamp().to_string()),
+        ("$iso8601", None) => Ok(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+        ("$randomInt", Some(args)) => {
+            let (min, max) = args
+                .split_once(':')
+                .ok_or_else(|| anyhow!("$randomInt takes a minimum and a maximum"))?;
+            let min: i64 = min.parse()?;
+            let max: i64 = max.parse()?;
+            if min > max {
+                return Err(anyhow!(
+                    "The minimum for $randomInt is more than the maximum"
+                ));
+            }
+
+            Ok(rand::thread_rng().gen_range(min..=max).to_string())
+        }
+        ("$date", Some(format)) => {
+            // Formatting panics on an invalid format string, so check it first
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(anyhow!("Invalid date format: {}", format));
+            }
+
+            Ok(Utc::now().format(format).to_string())
+        }
+        ("$uuid", _) | ("$timestamp", _) | ("$iso8601", _) | ("$randomInt", _) | ("$date", _) => {
+            Err(anyhow!("Wrong arguments for {}", name))
+        }
+        _ => Err(anyhow!("Unknown built-in variable: {}", name)),
+    }
+}
+
+fn is_builtin(name: &str) -> bool {
+    name.starts_with('$')
+}
+
+/// Resolves the values of variables, which can be templates themselves, like
+/// `base_url = "https://{host}/api"`. Each variable is only resolved once, so built-in variables in
+/// a value are generated once too.
+struct Resolver<'a> {
+    // Name -> unresolved value
+    variables: HashMap<&'a str, &'a str>,
+
+    resolved: HashMap<&'a str, String>,
+
+    // The variables that are currently being resolved, for detecting cycles
+    stack: Vec<&'a str>,
+
+    // Name -> value of the variables from the dotenv file, for `{$env.NAME}`
+    dotenv: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(variables: &'a [KeyValue], dotenv: &'a [KeyValue]) -> Resolver<'a> {
+        // Like when searching the list, the first binding for a name wins if there are duplicates
+        let mut by_name: HashMap<&str, &str> = HashMap::with_capacity(variables.len());
+        for var in variables {
+            by_name.entry(&var.name).or_insert(&var.value);
+        }
+
+        Resolver {
+            variables: by_name,
+            resolved: HashMap::new(),
+            stack: vec![],
+            dotenv: dotenv
+                .iter()
+                .map(|var| (var.name.as_str(), var.value.as_str()))
+                .collect(),
+        }
+    }
+
+    /// The resolved value of a variable, or None if it isn't bound
+    fn value(&mut self, name: &str) -> anyhow::Result<Option<&str>> {
+        let (name, unresolved) = match self.variables.get_key_value(name) {
+            Some((name, unresolved)) => (*name, *unresolved),
+            None => return Ok(None),
+        };
+
+        if !self.resolved.contains_key(name) {
+            if let Some(start) = self.stack.iter().position(|n| *n == name) {
+                let mut cycle = self.stack[start..].to_vec();
+                cycle.push(name);
+                return Err(anyhow!(
+                    "Variables reference each other in a cycle: {}",
+                    cycle.join(" -> ")
+                ));
+            }
+
+            self.stack.push(name);
+            let value = render(unresolved, self)?.0.into_owned();
+            self.stack.pop();
+
+            self.resolved.insert(name, value);
+        }
+
+        Ok(self.resolved.get(name).map(|value| value.as_str()))
+    }
+}
+
+/// Substitute the variables in a template in a single pass, so that the substituted values are
+/// never treated as templates again. Variables in the values themselves are resolved first,
+/// though. Unbound variables fall back to their default value if they have one, or are left as
+/// they are. Escaped braces (`\{` and `\}`) become literal braces. Fails if a variable uses a
+/// filter that doesn't exist, or if variables reference each other in a cycle.
+pub fn substitute<'a>(
+    base: &'a str,
+    variables: &'a [KeyValue],
+) -> anyhow::Result<(Cow<'a, str>, bool)> {
+    render(base, &mut Resolver::new(variables, &[]))
+}
+
+fn render<'b>(base: &'b str, resolver: &mut Resolver) -> anyhow::Result<(Cow<'b, str>, bool)> {
+    // Only allocated once there's something to replace
+    let mut output = String::new();
+    let mut replaced = false;
+
+    // The end of the part of `base` that has been copied to `output`
+    let mut copied = 0;
+
+    for caps in RE.captures_iter(base) {
+        let whole = caps.get(0).unwrap();
+        let replacement: Cow<str> = if let Some(brace) = caps.get(1) {
+            Cow::Borrowed(brace.as_str())
+        } else {
+            let placeholder = Placeholder::parse(caps.get(2).unwrap().as_str());
+            match evaluate(&placeholder, whole.as_str(), resolver)? {
+                Some(value) => value,
+                // Unbound, so left as it is
+                None => continue,
+            }
+        };
+
+        if !replaced {
+            output.reserve(base.len());
+            replaced = true;
+        }
+        output.push_str(&base[copied..whole.start()]);
+        output.push_str(&replacement);
+        copied = whole.end();
     }
 
     // If nothing was actually replaced, can just return the original reference. This extra boolean
     // flag is just Cow's `is_owned`, when that feature makes it to stable Rust we can remove this
     // flag.
-    if output == base {
-        (Cow::Borrowed(base), false)
+    if replaced {
+        output.push_str(&base[copied..]);
+        Ok((Cow::Owned(output), true))
     } else {
-        (Cow::Owned(output), true)
+        Ok((Cow::Borrowed(base), false))
     }
 }
 
-fn unbound_in_string(s: &str) -> Vec<&str> {
+// The value of a placeholder, after any filters, or None if it's unbound and has no default. `whole`
+// is the placeholder as it appears in the template, for error messages.
+fn evaluate<'r>(
+    placeholder: &Placeholder<'r>,
+    whole: &str,
+    resolver: &'r mut Resolver,
+) -> anyhow::Result<Option<Cow<'r, str>>> {
+    let value: Cow<str> = if is_builtin(placeholder.name) {
+        let value = builtin(placeholder.name, placeholder.default, &resolver.dotenv)
+            .with_context(|| format!("Failed substituting {}", whole))?;
+        Cow::Owned(value)
+    } else if let Some(value) = resolver.value(placeholder.name)? {
+        Cow::Borrowed(value)
+    } else if let Some(default) = placeholder.default {
+        Cow::Borrowed(default)
+    } else {
+        return Ok(None);
+    };
+
+    let value = if placeholder.filters.is_empty() {
+        value
+    } else {
+        let filtered = placeholder
+            .filters
+            .iter()
+            .try_fold(value.into_owned(), |value, filter| {
+                apply_filter(filter, value)
+            })
+            .with_context(|| format!("Failed substituting {}", whole))?;
+        Cow::Owned(filtered)
+    };
+
+    if let Some(value_type) = placeholder.value_type {
+        value_type
+            .to_json(&value)
+            .with_context(|| format!("Failed substituting {}", whole))?;
+    }
+
+    Ok(Some(value))
+}
+
+// A string that's nothing but one typed variable, like "{count:number}"
+fn typed_placeholder(s: &str) -> Option<Placeholder<'_>> {
+    let caps = RE.captures(s)?;
+    let whole = caps.get(0).unwrap();
+    let inner = caps.get(2)?;
+    if whole.start() != 0 || whole.end() != s.len() {
+        return None;
+    }
+
+    let placeholder = Placeholder::parse(inner.as_str());
+    placeholder.value_type.map(|_| placeholder)
+}
+
+// Substitute the variables in the strings (and object keys) of a typed JSON body
+fn render_json(value: &mut serde_json::Value, resolver: &mut Resolver) -> anyhow::Result<()> {
+    match value {
+        serde_json::Value::String(s) => {
+            if let Some(placeholder) = typed_placeholder(s) {
+                let typed = match evaluate(&placeholder, s, resolver)? {
+                    Some(replacement) => placeholder.value_type.unwrap().to_json(&replacement)?,
+                    // Unbound, so left as it is
+                    None => return Ok(()),
+                };
+                *value = typed;
+            } else {
+                let (new_value, is_owned) = render(s, resolver)?;
+                if is_owned {
+                    *s = new_value.into_owned();
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                render_json(item, resolver)?;
+            }
+        }
+        serde_json::Value::Object(map) => {
+            // Keys can't be changed in place, so the object is rebuilt
+            for (key, mut item) in std::mem::take(map) {
+                render_json(&mut item, resolver)?;
+                let key = render(&key, resolver)?.0.into_owned();
+                map.insert(key, item);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// All the strings in a JSON value, including object keys
+fn json_strings<'a>(value: &'a serde_json::Value, result: &mut Vec<&'a String>) {
+    match value {
+        serde_json::Value::String(s) => result.push(s),
+        serde_json::Value::Array(items) => {
+            for item in items {
+                json_strings(item, result);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (key, item) in map {
+                result.push(key);
+                json_strings(item, result);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Escape the braces in a value, so that it's bound exactly as it is instead of being treated as a
+/// template. See `KeyValue::literal`.
+pub fn escape(value: &str) -> String {
+    value.replace('{', "\\{").replace('}', "\\}")
+}
+
+fn placeholders_in_string(s: &str) -> Vec<Placeholder<'_>> {
     RE.captures_iter(s)
-        .map(|cap| cap.get(1).unwrap().as_str())
+        .filter_map(|cap| cap.get(2))
+        .map(|inner| Placeholder::parse(inner.as_str()))
         .collect()
 }
 
-/// List the variables (things like {var1}) that exist in a RequestDefinition.
-pub fn list_unbound_variables(request_definition: &RequestDefinition) -> Vec<&str> {
-    let mut result: Vec<&str> = vec![];
+fn unbound_in_string(s: &str, include_defaulted: bool) -> Vec<&str> {
+    placeholders_in_string(s)
+        .into_iter()
+        .filter(|placeholder| !is_builtin(placeholder.name))
+        .filter(|placeholder| include_defaulted || placeholder.default.is_none())
+        .map(|placeholder| placeholder.name)
+        .collect()
+}
 
-    // URL
-    result.append(&mut unbound_in_string(&request_definition.request.url));
+// All the strings in a RequestDefinition that can contain variables
+fn templates(def: &RequestDefinition) -> Vec<&String> {
+    let mut result: Vec<&String> = vec![&def.request.url];
 
     // Headers
-    for header in request_definition
-        .headers
-        .iter()
-        .flat_map(|h| h.headers.iter())
-    {
-        result.append(&mut unbound_in_string(&header.name));
-        result.append(&mut unbound_in_string(&header.value));
+    for header in def.headers.iter().flat_map(|h| h.headers.iter()) {
+        result.push(&header.name);
+        result.push(&header.value);
     }
 
     // Query params
-    for param in request_definition
-        .query
-        .iter()
-        .flat_map(|q| q.params.iter())
-    {
-        result.append(&mut unbound_in_string(&param.name));
-        result.append(&mut unbound_in_string(&param.value));
+    for param in def.query.iter().flat_map(|q| q.params.iter()) {
+        result.push(&param.name);
+        result.push(&param.value);
+    }
+
+    // Auth
+    match &def.auth {
+        Some(Auth::Basic { username, password }) => {
+            result.push(username);
+            result.extend(password.iter());
+        }
+        Some(Auth::Bearer { token }) => {
+            result.push(token);
+        }
+        Some(Auth::Digest { username, password }) => {
+            result.push(username);
+            result.push(password);
+        }
+        Some(Auth::OAuth2(oauth2)) => {
+            result.push(&oauth2.token_url);
+            result.push(&oauth2.client_id);
+            result.extend(
+                oauth2
+                    .client_secret
+                    .iter()
+                    .chain(oauth2.scopes.iter())
+                    .chain(oauth2.refresh_token.iter()),
+            );
+        }
+        Some(Auth::AwsSigV4(aws)) => {
+            result.push(&aws.region);
+            result.push(&aws.service);
+            result.push(&aws.access_key);
+            result.push(&aws.secret_key);
+            result.extend(aws.session_token.iter());
+        }
+        Some(Auth::Hmac(hmac)) => {
+            // Only the key, since the canonical string parts aren't templates
+            result.push(&hmac.key);
+        }
+        None => {}
     }
 
     // Body
-    match &request_definition.body {
+    match &def.body {
         Some(Content::Text(text)) => {
-            result.append(&mut unbound_in_string(&text));
+            result.push(text);
         }
         Some(Content::Json(json_string)) => {
-            result.append(&mut unbound_in_string(&json_string));
+            result.push(json_string);
+        }
+        Some(Content::TypedJson(value)) => {
+            json_strings(value, &mut result);
         }
         Some(Content::UrlEncoded(params)) => {
             for param in params {
-                result.append(&mut unbound_in_string(&param.name));
-                result.append(&mut unbound_in_string(&param.value));
+                result.push(&param.name);
+                result.push(&param.value);
+            }
+        }
+        Some(Content::Multipart(parts)) => {
+            for part in parts {
+                match part {
+                    Part::Text { name, value, .. } => {
+                        result.push(name);
+                        result.push(value);
+                    }
+                    Part::File {
+                        name,
+                        file,
+                        filename,
+                        ..
+                    } => {
+                        result.push(name);
+                        result.push(file);
+                        result.extend(filename.iter());
+                    }
+                }
+            }
+        }
+        Some(Content::File(file_body)) => {
+            // Only templated files that have already been loaded can contain variables
+            if let Some(FileContents::Text(text)) = &file_body.contents {
+                result.push(text);
             }
         }
         None => {}
     }
 
-    result.sort();
-    result.dedup();
     result
 }
 
-/// Mutate the provided RequestDefinition so that the provided variables are substituted into the
-/// URL, headers, query parameters, and body.
-pub fn substitute_all(def: &mut RequestDefinition, vars: &[KeyValue]) {
-    let (new_url, is_owned) = substitute(&def.request.url, vars);
-    if is_owned {
-        def.request.url = new_url.into_owned();
+// The same strings as `templates`, for substituting into
+fn templates_mut(def: &mut RequestDefinition) -> Vec<&mut String> {
+    let mut result: Vec<&mut String> = vec![&mut def.request.url];
+
+    for header in def.headers.iter_mut().flat_map(|h| h.headers.iter_mut()) {
+        result.push(&mut header.name);
+        result.push(&mut header.value);
     }
 
-    match &mut def.query {
-        Some(query) => {
-            for param in &mut query.params {
-                let (new_name, is_owned) = substitute(&param.name, vars);
-                if is_owned {
-                    param.name = new_name.into_owned();
-                }
-                let (new_value, is_owned) = substitute(&param.value, vars);
-                if is_owned {
-                    param.value = new_value.into_owned();
-                }
-            }
-        }
-        None => {}
+    for param in def.query.iter_mut().flat_map(|q| q.params.iter_mut()) {
+        result.push(&mut param.name);
+        result.push(&mut param.value);
     }
 
-    match &mut def.headers {
-        Some(headers) => {
-            for header in &mut headers.headers {
-                let (new_name, is_owned) = substitute(&header.name, vars);
-                if is_owned {
-                    header.name = new_name.into_owned();
-                }
-                let (new_value, is_owned) = substitute(&header.value, vars);
-                if is_owned {
-                    header.value = new_value.into_owned();
-                }
-            }
+    match &mut def.auth {
+        Some(Auth::Basic { username, password }) => {
+            result.push(username);
+            result.extend(password.iter_mut());
+        }
+        Some(Auth::Bearer { token }) => {
+            result.push(token);
+        }
+        Some(Auth::Digest { username, password }) => {
+            result.push(username);
+            result.push(password);
+        }
+        Some(Auth::OAuth2(oauth2)) => {
+            result.push(&mut oauth2.token_url);
+            result.push(&mut oauth2.client_id);
+            result.extend(
+                oauth2
+                    .client_secret
+                    .iter_mut()
+                    .chain(oauth2.scopes.iter_mut())
+                    .chain(oauth2.refresh_token.iter_mut()),
+            );
+        }
+        Some(Auth::AwsSigV4(aws)) => {
+            result.push(&mut aws.region);
+            result.push(&mut aws.service);
+            result.push(&mut aws.access_key);
+            result.push(&mut aws.secret_key);
+            result.extend(aws.session_token.iter_mut());
+        }
+        Some(Auth::Hmac(hmac)) => {
+            result.push(&mut hmac.key);
         }
         None => {}
     }
 
-    match def.body.as_mut() {
-        Some(Content::Text(text_content)) => {
-            let (new_content, is_owned) = substitute(&text_content, vars);
-            if is_owned {
-                *text_content = new_content.into_owned();
-            }
+    match &mut def.body {
+        Some(Content::Text(text)) => {
+            result.push(text);
         }
         Some(Content::Json(json_string)) => {
-            let (new_content, is_owned) = substitute(&json_string, vars);
-            if is_owned {
-                *json_string = new_content.into_owned();
-            }
+            result.push(json_string);
         }
+        // Substituted separately by `render_json`, since object keys can't be changed in place
+        Some(Content::TypedJson(_)) => {}
         Some(Content::UrlEncoded(params)) => {
             for param in params {
-                let (new_name, is_owned) = substitute(&param.name, vars);
-                if is_owned {
-                    param.name = new_name.into_owned();
-                }
-                let (new_value, is_owned) = substitute(&param.value, vars);
-                if is_owned {
-                    param.value = new_value.into_owned();
+                result.push(&mut param.name);
+                result.push(&mut param.value);
+            }
+        }
+        Some(Content::Multipart(parts)) => {
+            for part in parts {
+                match part {
+                    Part::Text { name, value, .. } => {
+                        result.push(name);
+                        result.push(value);
+                    }
+                    Part::File {
+                        name,
+                        file,
+                        filename,
+                        ..
+                    } => {
+                        result.push(name);
+                        result.push(file);
+                        result.extend(filename.iter_mut());
+                    }
                 }
             }
         }
+        Some(Content::File(file_body)) => {
+            if let Some(FileContents::Text(text)) = &mut file_body.contents {
+                result.push(text);
+            }
+        }
         None => {}
     }
+
+    result
+}
+
+// The templates in a RequestDefinition, plus the values of the variables that they use, and the
+// values of the variables that those use, and so on
+fn used_templates<'a>(def: &'a RequestDefinition, vars: &'a [KeyValue]) -> Vec<&'a str> {
+    let mut result: Vec<&str> = templates(def).into_iter().map(|t| t.as_str()).collect();
+
+    // Removed once used, so that each value is only added once
+    let mut unused: HashMap<&str, &str> = HashMap::with_capacity(vars.len());
+    for var in vars {
+        unused.entry(&var.name).or_insert(&var.value);
+    }
+
+    let mut i = 0;
+    while i < result.len() {
+        for placeholder in placeholders_in_string(result[i]) {
+            if let Some(value) = unused.remove(placeholder.name) {
+                result.push(value);
+            }
+        }
+        i += 1;
+    }
+
+    result
+}
+
+/// List the variables (things like {var1}) used in a RequestDefinition that aren't bound by `vars`.
+/// Variables that have a default value everywhere they're used, like {var1:value}, count as bound
+/// unless `include_defaulted` is true. So do the ones that are only used in the values of other
+/// variables, which are left as they are when they aren't bound.
+pub fn list_unbound_variables<'a>(
+    request_definition: &'a RequestDefinition,
+    vars: &'a [KeyValue],
+    include_defaulted: bool,
+) -> Vec<&'a str> {
+    let bound: HashSet<&str> = vars.iter().map(|var| var.name.as_str()).collect();
+
+    // The definition's own templates come first, followed by the values of the variables they use
+    let own_templates = templates(request_definition).len();
+    let mut result: Vec<&str> = used_templates(request_definition, vars)
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| include_defaulted || *i < own_templates)
+        .flat_map(|(_, template)| unbound_in_string(template, include_defaulted))
+        .filter(|name| !bound.contains(name))
+        .collect();
+
+    result.sort();
+    result.dedup();
+    result
+}
+
+/// The default value of each variable that has one in a RequestDefinition, or in the values of the
+/// variables it uses. If a variable is given different defaults in different places, the first one
+/// is used.
+pub fn list_defaults<'a>(
+    request_definition: &'a RequestDefinition,
+    vars: &'a [KeyValue],
+) -> HashMap<&'a str, &'a str> {
+    let mut result: HashMap<&str, &str> = HashMap::new();
+    for placeholder in used_templates(request_definition, vars)
+        .into_iter()
+        .flat_map(placeholders_in_string)
+    {
+        if let (false, Some(default)) = (is_builtin(placeholder.name), placeholder.default) {
+            result.entry(placeholder.name).or_insert(default);
+        }
+    }
+
+    result
+}
+
+/// Mutate the provided RequestDefinition so that the provided variables are substituted into the
+/// URL, headers, query parameters, auth, and body. This should be done once, with every variable
+/// that's going to be bound, since escaped braces are unescaped at the same time. `dotenv` is the
+/// variables from the dotenv file, which `{$env.NAME}` looks in before the process environment.
+pub fn substitute_all(
+    def: &mut RequestDefinition,
+    vars: &[KeyValue],
+    dotenv: &[KeyValue],
+) -> anyhow::Result<()> {
+    // Shared by all the templates, so each variable is only resolved once
+    let mut resolver = Resolver::new(vars, dotenv);
+
+    for (name, declaration) in &def.variables {
+        if let Some(value) = resolver.value(name)? {
+            declaration
+                .validate(value)
+                .with_context(|| format!("Invalid value for the variable {}", name))?;
+        }
+    }
+
+    for template in templates_mut(def) {
+        let (new_value, is_owned) = render(template, &mut resolver)?;
+        if is_owned {
+            *template = new_value.into_owned();
+        }
+    }
+
+    if let Some(Content::TypedJson(value)) = &mut def.body {
+        render_json(value, &mut resolver)?;
+    }
+
+    Ok(())
 }
 
 #[test]
@@ -169,7 +738,7 @@ fn test_substitute() {
     ];
     let base = "a {var2} b {var1} c {var3} d {var2}";
 
-    let (new_string, is_owned) = substitute(base, &vars);
+    let (new_string, is_owned) = substitute(base, &vars).unwrap();
 
     assert_eq!(new_string, "a value2 b value1 c {var3} d value2");
 
@@ -179,10 +748,435 @@ fn test_substitute() {
 #[test]
 fn test_unbound_in_string() {
     assert_eq!(
-        unbound_in_string("one two {three} four {five} six"),
+        unbound_in_string("one two {three} four {five} six", false),
         vec!["three", "five"]
     );
 
     let blank: Vec<&str> = Vec::new();
-    assert_eq!(unbound_in_string("no variables"), blank);
+    assert_eq!(unbound_in_string("no variables", false), blank);
+
+    assert_eq!(
+        unbound_in_string("{one} {two:2} {three:}", false),
+        vec!["one"]
+    );
+    assert_eq!(
+        unbound_in_string("{one} {two:2} {three:}", true),
+        vec!["one", "two", "three"]
+    );
+}
+
+#[test]
+fn test_defaults() {
+    let vars = vec![KeyValue::new("port", "8080")];
+    let base = "http://{host:localhost}:{port:80}/{path:a:b}";
+
+    let (new_string, _) = substitute(base, &vars).unwrap();
+    assert_eq!(new_string, "http://localhost:8080/a:b");
+
+    let mut def: RequestDefinition = toml::from_str(
+        r#"
+        [request]
+        url = "http://{host:localhost}:{port:80}/{path:a:b}"
+        method = "GET"
+
+        [headers]
+        headers = [{ name = "X-Host", value = "{host:other}" }]
+        "#,
+    )
+    .unwrap();
+
+    let defaults = list_defaults(&def, &vars);
+    assert_eq!(defaults.get("host"), Some(&"localhost"));
+    assert_eq!(defaults.get("path"), Some(&"a:b"));
+
+    let blank: Vec<&str> = Vec::new();
+    assert_eq!(list_unbound_variables(&def, &vars, false), blank);
+    assert_eq!(
+        list_unbound_variables(&def, &vars, true),
+        vec!["host", "path"]
+    );
+
+    substitute_all(&mut def, &vars, &[]).unwrap();
+    assert_eq!(def.request.url, "http://localhost:8080/a:b");
+    assert_eq!(def.headers.unwrap().headers[0].value, "other");
+}
+
+#[test]
+fn test_inline_json_objects() {
+    let vars = vec![KeyValue::new("id", "5")];
+
+    let base = r#"{"a": 1} and {"nested": {"b": 2}} and {"user": {"id": {id}}}"#;
+    let blank: Vec<&str> = Vec::new();
+    assert_eq!(
+        unbound_in_string(r#"{"a": 1} {"b": {"c": 2}}"#, true),
+        blank
+    );
+    assert_eq!(unbound_in_string(base, true), vec!["id"]);
+    assert_eq!(
+        substitute(base, &vars).unwrap().0,
+        r#"{"a": 1} and {"nested": {"b": 2}} and {"user": {"id": 5}}"#
+    );
+
+    // Not identifiers, so not variables
+    let base = "{1} {a b} {:x} {-a}";
+    assert_eq!(unbound_in_string(base, true), blank);
+    assert_eq!(
+        substitute(base, &vars).unwrap(),
+        (Cow::Borrowed(base), false)
+    );
+}
+
+#[test]
+fn test_escaped_braces() {
+    let vars = vec![KeyValue::new("name", "value")];
+
+    let base = r"\{\{ .Name \}\} {name} \{name\}";
+    assert_eq!(unbound_in_string(base, true), vec!["name"]);
+    assert_eq!(
+        substitute(base, &vars).unwrap().0,
+        "{{ .Name }} value {name}"
+    );
+
+    // Neither a JSON object nor a value containing one needs escaping
+    let vars = vec![KeyValue::new("inner", r#"{"b": {"c": "{d}"}}"#)];
+    let base = r#"{"a": {inner}}"#;
+    assert_eq!(unbound_in_string(base, true), vec!["inner"]);
+    assert_eq!(
+        substitute(base, &vars).unwrap().0,
+        r#"{"a": {"b": {"c": "{d}"}}}"#
+    );
+}
+
+#[test]
+fn test_filters() {
+    let vars = vec![
+        KeyValue::new("q", "a b&c=d/é"),
+        KeyValue::new("password", r#"say "hi"\"#),
+        KeyValue::new("creds", "user:pass"),
+    ];
+
+    let substituted = |base: &str| substitute(base, &vars).unwrap().0.into_owned();
+
+    assert_eq!(substituted("{q|urlencode}"), "a%20b%26c%3Dd%2F%C3%A9");
+    assert_eq!(substituted(r#""{password|json}""#), r#""say \"hi\"\\""#);
+    assert_eq!(substituted("Basic {creds|base64}"), "Basic dXNlcjpwYXNz");
+    assert_eq!(substituted("{creds|upper}"), "USER:PASS");
+    assert_eq!(substituted("{missing:ABC|lower}"), "abc");
+    assert_eq!(
+        substituted("{creds|upper|sha256}"),
+        "410d2259d7dd5b19091fba6058cc2912d721cbaa4f497774ac07af46a600c178"
+    );
+
+    // Filters on unbound variables are left alone, like the variables themselves
+    assert_eq!(substituted("{missing|upper}"), "{missing|upper}");
+    assert_eq!(unbound_in_string("{missing|upper}", false), vec!["missing"]);
+
+    assert!(substitute("{q|nope}", &vars).is_err());
+}
+
+#[test]
+fn test_builtins() {
+    let vars: Vec<KeyValue> = vec![];
+    let substituted = |base: &str| substitute(base, &vars).unwrap().0.into_owned();
+
+    let uuid = substituted("{$uuid}");
+    assert!(Uuid::parse_str(&uuid).is_ok());
+    assert_ne!(substituted("{$uuid}"), uuid);
+
+    let timestamp: i64 = substituted("{$timestamp}").parse().unwrap();
+    assert!((Utc::now().timestamp() - timestamp).abs() < 5);
+
+    assert!(chrono::DateTime::parse_from_rfc3339(&substituted("{$iso8601}")).is_ok());
+    assert_eq!(
+        substituted("{$date:%Y-%m-%d}"),
+        Utc::now().format("%Y-%m-%d").to_string()
+    );
+
+    for _ in 0..20 {
+        let random: i64 = substituted("{$randomInt:-2:2}").parse().unwrap();
+        assert!((-2..=2).contains(&random));
+    }
+    assert_eq!(substituted("{$randomInt:7:7|upper}"), "7");
+
+    // Built-in variables never need to be bound
+    let blank: Vec<&str> = Vec::new();
+    assert_eq!(unbound_in_string("{$uuid} {$date:%Y}", true), blank);
+
+    for bad in &[
+        "{$nope}",
+        "{$uuid:1}",
+        "{$randomInt}",
+        "{$randomInt:5:1}",
+        "{$randomInt:a:b}",
+        "{$date:%Q}",
+    ] {
+        assert!(substitute(bad, &vars).is_err(), "{} should fail", bad);
+    }
+}
+
+#[test]
+fn test_env_variables() {
+    // Set by Cargo when running tests, so nothing has to change the process environment
+    let vars: Vec<KeyValue> = vec![KeyValue::new("$env.CARGO_PKG_NAME", "ignored")];
+
+    assert_eq!(
+        substitute("{$env.CARGO_PKG_NAME|upper}", &vars).unwrap().0,
+        "RHC"
+    );
+    assert_eq!(
+        substitute("{$env.RHC_TEMPLATING_MISSING:fallback}", &vars)
+            .unwrap()
+            .0,
+        "fallback"
+    );
+    assert!(substitute("{$env.RHC_TEMPLATING_MISSING}", &vars).is_err());
+
+    // Variables from the dotenv file come first
+    let dotenv = vec![
+        KeyValue::new("CARGO_PKG_NAME", "from dotenv"),
+        KeyValue::new("RHC_TEMPLATING_MISSING", "found"),
+    ];
+    let mut resolver = Resolver::new(&vars, &dotenv);
+    assert_eq!(
+        render(
+            "{$env.CARGO_PKG_NAME} {$env.RHC_TEMPLATING_MISSING}",
+            &mut resolver
+        )
+        .unwrap()
+        .0,
+        "from dotenv found"
+    );
+
+    let blank: Vec<&str> = Vec::new();
+    assert_eq!(
+        unbound_in_string("{$env.RHC_TEMPLATING_MISSING}", true),
+        blank
+    );
+}
+
+#[test]
+fn test_nested_variables() {
+    let vars = vec![
+        KeyValue::new("base_url", "https://{host}:{port:443}/api"),
+        KeyValue::new("host", "{subdomain}.example.com"),
+        KeyValue::new("subdomain", "staging"),
+        KeyValue::new("id", "{$uuid}"),
+        KeyValue::new("extracted", &escape(r#"{"a": "{host}"}"#)),
+    ];
+
+    assert_eq!(
+        substitute("{base_url}/users", &vars).unwrap().0,
+        "https://staging.example.com:443/api/users"
+    );
+    assert_eq!(
+        substitute("{extracted}", &vars).unwrap().0,
+        r#"{"a": "{host}"}"#
+    );
+
+    // Each variable is only resolved once
+    let mut def: RequestDefinition = toml::from_str(
+        r#"
+        [request]
+        url = "http://localhost/{id}"
+        method = "GET"
+
+        [headers]
+        headers = [{ name = "X-Id", value = "{id}" }]
+        "#,
+    )
+    .unwrap();
+    substitute_all(&mut def, &vars, &[]).unwrap();
+    assert_eq!(
+        def.request.url,
+        format!("http://localhost/{}", def.headers.unwrap().headers[0].value)
+    );
+
+    // Variables used in the values of other variables are left as they are when they aren't bound,
+    // so they're only listed along with the defaulted ones
+    let def: RequestDefinition = toml::from_str(
+        r#"
+        [request]
+        url = "{base_url}"
+        method = "GET"
+        "#,
+    )
+    .unwrap();
+    let vars = vec![
+        KeyValue::new("base_url", "https://{host}:{port:443}/api"),
+        KeyValue::new("unused", "{other}"),
+    ];
+    let blank: Vec<&str> = Vec::new();
+    assert_eq!(list_unbound_variables(&def, &vars, false), blank);
+    assert_eq!(
+        list_unbound_variables(&def, &vars, true),
+        vec!["host", "port"]
+    );
+    assert_eq!(
+        substitute("{base_url}", &vars).unwrap().0,
+        "https://{host}:443/api"
+    );
+    assert_eq!(list_defaults(&def, &vars).get("port"), Some(&"443"));
+
+    // Environment values that were written before variables could be nested, like JSON, are
+    // still bound as they are
+    let vars = vec![KeyValue::new(
+        "payload",
+        r#"{"id": 5, "user": {"name": "{name}"}}"#,
+    )];
+    assert_eq!(
+        substitute("{payload}", &vars).unwrap().0,
+        r#"{"id": 5, "user": {"name": "{name}"}}"#
+    );
+}
+
+#[test]
+fn test_variable_cycles() {
+    let vars = vec![
+        KeyValue::new("a", "{b}"),
+        KeyValue::new("b", "x{c}"),
+        KeyValue::new("c", "{a}"),
+        KeyValue::new("d", "{d}"),
+    ];
+
+    let error = substitute("{b}", &vars).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Variables reference each other in a cycle: b -> c -> a -> b"
+    );
+
+    let error = substitute("{d}", &vars).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Variables reference each other in a cycle: d -> d"
+    );
+
+    // Listing unbound variables terminates in spite of the cycle
+    let def: RequestDefinition = toml::from_str(
+        r#"
+        [request]
+        url = "{a}"
+        method = "GET"
+        "#,
+    )
+    .unwrap();
+    let blank: Vec<&str> = Vec::new();
+    assert_eq!(list_unbound_variables(&def, &vars, true), blank);
+}
+
+#[test]
+fn test_typed_placeholders() {
+    assert_eq!(
+        Placeholder::parse("count:number:1|lower"),
+        Placeholder {
+            name: "count",
+            value_type: Some(ValueType::Number),
+            default: Some("1"),
+            filters: vec!["lower"],
+        }
+    );
+    assert_eq!(
+        Placeholder::parse("flag:bool"),
+        Placeholder {
+            name: "flag",
+            value_type: Some(ValueType::Bool),
+            default: None,
+            filters: vec![],
+        }
+    );
+    assert_eq!(Placeholder::parse("name:numbers").default, Some("numbers"));
+
+    let vars = vec![KeyValue::new("count", "12.5"), KeyValue::new("flag", "yes")];
+    assert_eq!(
+        substitute("/items?limit={count:number}", &vars).unwrap().0,
+        "/items?limit=12.5"
+    );
+    assert_eq!(
+        substitute("{flag:bool}", &vars).unwrap_err().to_string(),
+        "Failed substituting {flag:bool}"
+    );
+    assert!(substitute("{missing:number:abc}", &vars).is_err());
+
+    // Required unless there's a default after the type
+    let def: RequestDefinition = toml::from_str(
+        r#"
+        [request]
+        url = "http://localhost/{page:number:1}?limit={limit:number}"
+        method = "GET"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(list_unbound_variables(&def, &vars, false), vec!["limit"]);
+    assert_eq!(list_defaults(&def, &vars).get("page"), Some(&"1"));
+}
+
+#[test]
+fn test_typed_json() {
+    let vars = vec![
+        KeyValue::new("count", "3"),
+        KeyValue::new("flag", "false"),
+        KeyValue::new("key", "dynamic"),
+        KeyValue::new("name", &escape(r#"quote " and {braces}"#)),
+    ];
+    let mut def: RequestDefinition = toml::from_str(
+        r#"
+        [request]
+        url = "http://localhost"
+        method = "POST"
+
+        [body]
+        type = "typed_json"
+        content = '''
+        {
+            "count": "{count:number}",
+            "enabled": "{flag:bool}",
+            "page": "{page:number:1}",
+            "label": "{count:number} items",
+            "name": "{name}",
+            "{key}": ["{count}", null]
+        }
+        '''
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(list_unbound_variables(&def, &vars, true), vec!["page"]);
+
+    substitute_all(&mut def, &vars, &[]).unwrap();
+    match def.body {
+        Some(Content::TypedJson(value)) => assert_eq!(
+            value,
+            serde_json::json!({
+                "count": 3,
+                "enabled": false,
+                "page": 1,
+                "label": "3 items",
+                "name": r#"quote " and {braces}"#,
+                "dynamic": ["3", null]
+            })
+        ),
+        _ => panic!("Expected a typed JSON body"),
+    }
+}
+
+#[test]
+fn test_typed_secret_not_in_error() {
+    let mut def: RequestDefinition = toml::from_str(
+        r#"
+        [request]
+        url = "http://localhost"
+        method = "POST"
+
+        [variables.pin]
+        secret = true
+
+        [body]
+        type = "typed_json"
+        content = '{"pin": "{pin:number}"}'
+        "#,
+    )
+    .unwrap();
+
+    let vars = vec![KeyValue::new("pin", "s3cret")];
+    let error = format!("{:#}", substitute_all(&mut def, &vars, &[]).unwrap_err());
+    assert_eq!(error, "Failed substituting {pin:number}: Expected a number");
 }
@@ -0,0 +1,129 @@
+use crate::http::RequestContext;
+use crate::keyvalue;
+use crate::keyvalue::KeyValue;
+use crate::request_definition::RequestDefinition;
+use crate::runner;
+use crate::runner::TestResult;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+fn default_stop_on_failure() -> bool {
+    true
+}
+
+/// An ordered sequence of request definitions to send, sharing one set of variables
+#[derive(Deserialize, Debug)]
+pub struct Workflow {
+    pub name: Option<String>,
+
+    /// Whether to skip the remaining steps once one of them fails
+    #[serde(default = "default_stop_on_failure")]
+    pub stop_on_failure: bool,
+
+    pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Step {
+    /// Path to the request definition file, relative to the request definition directory
+    pub definition: String,
+
+    /// Bindings that only apply to this step, overriding any shared variables
+    #[serde(default)]
+    pub bindings: Vec<KeyValue>,
+}
+
+/// The results of the steps that were run, plus all the values extracted along the way
+pub struct WorkflowResult {
+    pub steps: Vec<TestResult>,
+    pub extracted: Vec<KeyValue>,
+}
+
+impl Workflow {
+    pub fn new(path: &Path) -> anyhow::Result<Workflow> {
+        let contents = std::fs::read_to_string(path)?;
+        let workflow = toml::from_str(&contents)?;
+
+        Ok(workflow)
+    }
+
+    /// Whether a TOML file is a workflow rather than a request definition, for skipping workflows
+    /// that are kept alongside the definitions they use
+    pub fn is_workflow(path: &Path) -> bool {
+        RequestDefinition::new(path).is_err() && Workflow::new(path).is_ok()
+    }
+
+    /// Run each step in order. `vars` must be sorted by name; values extracted by a step are bound
+    /// for all the steps that come after it. `on_step` is called as soon as each step finishes, and
+    /// any error it returns stops the workflow.
+    pub fn run<F>(
+        &self,
+        mut vars: Vec<KeyValue>,
+        context: &mut RequestContext,
+        mut on_step: F,
+    ) -> anyhow::Result<WorkflowResult>
+    where
+        F: FnMut(&TestResult) -> anyhow::Result<()>,
+    {
+        let base_dir = PathBuf::from(
+            shellexpand::tilde(&context.config.request_definition_directory).as_ref(),
+        );
+
+        let mut result = WorkflowResult {
+            steps: Vec::new(),
+            extracted: Vec::new(),
+        };
+
+        for step in &self.steps {
+            let mut step_vars: Vec<KeyValue> = vars.clone();
+            keyvalue::merge(&mut step_vars, step.bindings.clone());
+
+            let path = base_dir.join(shellexpand::tilde(&step.definition).as_ref());
+            let step_result = runner::run_test(&path, &step.definition, &step_vars, context);
+            on_step(&step_result)?;
+
+            let extracted = step_result
+                .extracted
+                .iter()
+                .map(|var| KeyValue::literal(&var.name, &var.value))
+                .collect();
+            keyvalue::merge(&mut vars, extracted);
+            keyvalue::merge(&mut result.extracted, step_result.extracted.clone());
+
+            let passed = step_result.passed();
+            result.steps.push(step_result);
+
+            if !passed && self.stop_on_failure {
+                break;
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[test]
+fn test_parse_workflow() {
+    let workflow: Workflow = toml::from_str(
+        r#"
+        name = "Order lifecycle"
+
+        [[steps]]
+        definition = "users/create.toml"
+        bindings = [{ name = "role", value = "admin" }]
+
+        [[steps]]
+        definition = "orders/create.toml"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(workflow.name.as_deref(), Some("Order lifecycle"));
+    assert!(workflow.stop_on_failure);
+    assert_eq!(workflow.steps.len(), 2);
+    assert_eq!(
+        workflow.steps[0].bindings,
+        vec![KeyValue::new("role", "admin")]
+    );
+    assert!(workflow.steps[1].bindings.is_empty());
+}
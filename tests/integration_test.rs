@@ -353,6 +353,151 @@ fn test_templating_string() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_templating_braces() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/json"
+
+    [body]
+    type = "json"
+    content = '''
+    {
+        "nested": {nested},
+        "inline": {"a": {"b": [{"c": 1}]}},
+        "template": "\{\{ .Name \}\}"
+    }
+    '''
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "nested", value = '{"a": {"b": "{c}"}}' },
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/json"),
+            request::body(json_decoded(eq(serde_json::json!({
+                "nested": { "a": { "b": "{c}" } },
+                "inline": { "a": { "b": [{ "c": 1 }] } },
+                "template": "{{ .Name }}"
+            }))))
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_templating_filters() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/search?q={query|urlencode}"
+
+    [headers]
+    headers = [
+      { name = "Authorization", value = "Basic {credentials|base64}" },
+    ]
+
+    [body]
+    type = "json"
+    content = '''
+    {
+        "password": "{password|json}"
+    }
+    '''
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "query", value = "a&b=c" },
+          { name = "credentials", value = "user:pass" },
+          { name = "password", value = 'p"w\d' },
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/search"),
+            request::query(url_decoded(contains(("q", "a&b=c")))),
+            request::headers(contains(("authorization", "Basic dXNlcjpwYXNz"))),
+            request::body(json_decoded(eq(serde_json::json!({
+                "password": "p\"w\\d"
+            }))))
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_unknown_filter() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/{var1|nope}"
+    "#,
+        None,
+    )?;
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--binding");
+    cmd.arg("var1=bar");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown filter: nope"));
+
+    Ok(())
+}
+
+#[test]
+fn test_templating_builtins() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/items?n={$randomInt:3:3}"
+
+    [headers]
+    headers = [
+      { name = "Idempotency-Key", value = "{$uuid}" },
+    ]
+    "#,
+        None,
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/items"),
+            request::query(url_decoded(contains(("n", "3")))),
+            request::headers(contains(key("idempotency-key"))),
+            request::headers(not(contains(("idempotency-key", "{$uuid}")))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
 #[test]
 fn test_templating_urlencoded() -> anyhow::Result<()> {
     let fixture = setup(
@@ -514,27 +659,1683 @@ fn test_bindings_overwrite() -> anyhow::Result<()> {
 }
 
 #[test]
-fn test_duplicate_vars_in_env() -> anyhow::Result<()> {
+fn test_default_values() -> anyhow::Result<()> {
     let fixture = setup(
         r#"
     [request]
     method = "GET"
-    url = "__base_url__/foo"
+    url = "__base_url__/{var1:first}/{var2:second}"
+    "#,
+        None,
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(request::method_path("GET", "/first/bound"))
+            .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--binding");
+    cmd.arg("var2=bound");
+    cmd.assert().success();
+
+    // Asking to be prompted for the defaulted variables requires a TTY
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--prompt-defaults");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Running in interactive mode requires a TTY\n",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_inline_json_objects() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/text"
+
+    [body]
+    type = "text"
+    content = '{"a": 1} and {"nested": {"b": 2}} for {name}'
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [{ name = "name", value = "alice" }]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/text"),
+            request::body(r#"{"a": 1} and {"nested": {"b": 2}} for alice"#)
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/json"
+
+    [body]
+    type = "json"
+    content = '{"user": {"id": {id}, "name": "{name}"}, "tags": [{"a": 1}]}'
     "#,
         Some(
             r#"
         name = "test_env"
         variables = [
-          { name = "var1", value = "original" },
-          { name = "var1", value = "duplicate" },
+          { name = "id", value = "5" },
+          { name = "name", value = "alice" },
         ]
     "#,
         ),
     )?;
 
-    let assert = run(fixture);
-    assert.failure().stderr(predicate::str::contains(
-        "contains duplicate bindings for: var1",
-    ));
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/json"),
+            request::body(json_decoded(eq(serde_json::json!({
+                "user": { "id": 5, "name": "alice" },
+                "tags": [{ "a": 1 }]
+            }))))
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_dotenv() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/{var1}/{var2}/{var3}/{$env.FROM_PROCESS}/{$env.VAR2}/{$env.SHARED}"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "var1", value = "env_file" },
+          { name = "var2", value = "env_file" },
+          { name = "var3", value = "env_file" },
+        ]
+    "#,
+        ),
+    )?;
+
+    let dir = tempfile::tempdir()?;
+    let dotenv = dir.path().join(".env");
+    std::fs::write(
+        &dotenv,
+        "var2=dotenv\nvar3=dotenv\nVAR2=exported\nSHARED=dotenv\n",
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(request::method_path(
+            "GET",
+            "/env_file/dotenv/binding/process/exported/dotenv",
+        ))
+        .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--environment");
+    cmd.arg(fixture.env_file.as_ref().unwrap().path());
+    cmd.arg("--dotenv");
+    cmd.arg(&dotenv);
+    cmd.arg("--binding");
+    cmd.arg("var3=binding");
+    cmd.env("FROM_PROCESS", "process");
+    cmd.env_remove("VAR2");
+    cmd.env("SHARED", "process");
+    cmd.assert().success();
+
+    Ok(())
+}
+
+#[test]
+fn test_command_variables() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/{token}"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = []
+        commands = [
+          { name = "token", command = "echo from_command" },
+          { name = "unused", command = "exit 1" },
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(request::method_path("GET", "/from_command"))
+            .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_failing_command_variable() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/{token}"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = []
+        commands = [
+          { name = "token", command = "echo 'not logged in' >&2; exit 2" },
+        ]
+    "#,
+        ),
+    )?;
+
+    run(fixture).failure().stderr(
+        predicate::str::contains("The command for the variable token failed")
+            .and(predicate::str::contains("not logged in")),
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_nested_variables() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "{base_url}/users"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "base_url", value = "__base_url__/{version}" },
+        ]
+    "#,
+        ),
+    )?;
+
+    // The environment file isn't run through `setup`'s replacement
+    let env_content = std::fs::read_to_string(fixture.env_file.as_ref().unwrap().path())?
+        .replace("__base_url__/", &fixture.server.url_str(""));
+    std::fs::write(fixture.env_file.as_ref().unwrap().path(), env_content)?;
+
+    fixture.server.expect(
+        Expectation::matching(request::method_path("GET", "/v2/users"))
+            .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--environment");
+    cmd.arg(fixture.env_file.as_ref().unwrap().path());
+    cmd.arg("--binding");
+    cmd.arg("version=v2");
+    cmd.assert().success();
+
+    // With the binding missing, the variable used by the environment's variable is only prompted
+    // for along with the defaulted ones
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--prompt-defaults");
+    cmd.arg("--environment");
+    cmd.arg(fixture.env_file.as_ref().unwrap().path());
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Running in interactive mode requires a TTY\n",
+    ));
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--environment");
+    cmd.arg(fixture.env_file.as_ref().unwrap().path());
+    cmd.arg("--binding");
+    cmd.arg("version={base_url}");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Variables reference each other in a cycle: base_url -> version -> base_url",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_declared_variables() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/{stage}/items?limit={limit}"
+
+    [variables.stage]
+    description = "Which deployment to call"
+    values = ["dev", "prod"]
+
+    [variables.limit]
+    type = "int"
+    "#,
+        None,
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/dev/items"),
+            request::query(url_decoded(contains(("limit", "10")))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--binding");
+    cmd.arg("stage=dev");
+    cmd.arg("--binding");
+    cmd.arg("limit=10");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--binding");
+    cmd.arg("stage=qa");
+    cmd.arg("--binding");
+    cmd.arg("limit=10");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Invalid value for the variable stage: Expected one of dev, prod, but got 'qa'",
+    ));
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--binding");
+    cmd.arg("stage=dev");
+    cmd.arg("--binding");
+    cmd.arg("limit=ten");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Invalid value for the variable limit: Expected an integer, but got 'ten'",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_typed_json_body() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/items"
+
+    [body]
+    type = "typed_json"
+    content = '''
+    {
+        "count": "{count:number}",
+        "enabled": "{enabled:bool:true}",
+        "name": "{name}"
+    }
+    '''
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "count", value = "42" },
+          { name = "name", value = 'say "hi"' },
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/items"),
+            request::body(json_decoded(eq(serde_json::json!({
+                "count": 42,
+                "enabled": true,
+                "name": "say \"hi\""
+            }))))
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--environment");
+    cmd.arg(fixture.env_file.as_ref().unwrap().path());
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--environment");
+    cmd.arg(fixture.env_file.as_ref().unwrap().path());
+    cmd.arg("--binding");
+    cmd.arg("count=many");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Failed substituting {count:number}",
+        ))
+        .stderr(predicate::str::contains("Expected a number"))
+        .stderr(predicate::str::contains("many").not());
+
+    Ok(())
+}
+
+#[test]
+fn test_environment_variables_table() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/{path}"
+
+    [body]
+    type = "json"
+    content = '''
+    {
+        "port": {port},
+        "debug": {debug},
+        "tags": {tags},
+        "limits": {limits}
+    }
+    '''
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+
+        [variables]
+        path = "items"
+        port = 8080
+        debug = true
+        tags = ["a", "b"]
+        limits = { max = 10 }
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/items"),
+            request::body(json_decoded(eq(serde_json::json!({
+                "port": 8080,
+                "debug": true,
+                "tags": ["a", "b"],
+                "limits": { "max": 10 }
+            }))))
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.arg("--environment");
+    cmd.arg(fixture.env_file.as_ref().unwrap().path());
+    cmd.assert().success();
+
+    Ok(())
+}
+
+#[test]
+fn test_duplicate_vars_in_env() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/foo"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "var1", value = "original" },
+          { name = "var1", value = "duplicate" },
+        ]
+    "#,
+        ),
+    )?;
+
+    let assert = run(fixture);
+    assert.failure().stderr(predicate::str::contains(
+        "contains duplicate bindings for: var1",
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_post_multipart() -> anyhow::Result<()> {
+    let mut upload = NamedTempFile::new()?;
+    write!(upload, "file contents")?;
+    let mut relative = NamedTempFile::new()?;
+    write!(relative, "relative contents")?;
+
+    // The def file is created in the same temp directory, so a file can be referenced by a
+    // relative path
+    let fixture = setup(
+        &r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/foo"
+
+    [body]
+    type = "multipart"
+    content = [
+      { name = "description", value = "{var1}" },
+      { name = "upload", file = "__upload__", filename = "{var1}.txt", content_type = "text/plain" },
+      { name = "relative", file = "__relative__" }
+    ]
+    "#
+        .replace("__upload__", &upload.path().to_string_lossy())
+        .replace(
+            "__relative__",
+            &relative.path().file_name().unwrap().to_string_lossy(),
+        ),
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "var1", value = "bar" }
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/foo"),
+            request::headers(contains((
+                "content-type",
+                matches("^multipart/form-data; boundary=")
+            ))),
+            request::body(matches(
+                "Content-Disposition: form-data; name=\"description\"\r\n\r\nbar\r\n"
+            )),
+            request::body(matches(
+                "Content-Disposition: form-data; name=\"upload\"; filename=\"bar.txt\"\r\nContent-Type: text/plain\r\n\r\nfile contents\r\n"
+            )),
+            request::body(matches(
+                "name=\"relative\"; filename=\"[^\"]+\"\r\nContent-Type: application/octet-stream\r\n\r\nrelative contents\r\n"
+            )),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_post_file_bytes() -> anyhow::Result<()> {
+    let mut body_file = NamedTempFile::new()?;
+    body_file.write_all(&[0, 159, 146, 150, b'{', b'a', b'}'])?;
+
+    // The def file is created in the same temp directory, so the body file can be referenced by a
+    // relative path
+    let fixture = setup(
+        &r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/foo"
+
+    [body]
+    type = "file"
+    content = { path = "__body_file__" }
+    "#
+        .replace(
+            "__body_file__",
+            &body_file.path().file_name().unwrap().to_string_lossy(),
+        ),
+        None,
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/foo"),
+            request::headers(contains(("content-type", "application/octet-stream"))),
+            request::body(&[0, 159, 146, 150, b'{', b'a', b'}'][..]),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_post_file_templated() -> anyhow::Result<()> {
+    let mut body_file = NamedTempFile::new()?;
+    write!(body_file, "{{\n  \"key\": \"{{var1}}\"\n}}")?;
+
+    let fixture = setup(
+        &r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/foo"
+
+    [body]
+    type = "file"
+    content = { path = "__body_file__", template = true, content_type = "application/json" }
+    "#
+        .replace("__body_file__", &body_file.path().to_string_lossy()),
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "var1", value = "bar" }
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/foo"),
+            request::headers(contains(("content-type", "application/json"))),
+            request::body(json_decoded(eq(serde_json::json!({ "key": "bar" })))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_post_file_stdin() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/foo"
+
+    [body]
+    type = "file"
+    content = { path = "-" }
+    "#,
+        None,
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/foo"),
+            request::body("from stdin"),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg(fixture.def_file.path());
+    cmd.write_stdin("from stdin");
+
+    cmd.assert().success();
+    Ok(())
+}
+
+#[test]
+fn test_basic_auth() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/foo"
+
+    [auth]
+    type = "basic"
+    username = "{user}"
+    password = "{password}"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "user", value = "aladdin" },
+          { name = "password", value = "opensesame" }
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/foo"),
+            request::headers(contains((
+                "authorization",
+                "Basic YWxhZGRpbjpvcGVuc2VzYW1l"
+            ))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_bearer_auth() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/foo"
+
+    [auth]
+    type = "bearer"
+    token = "{token}"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "token", value = "abc123" }
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/foo"),
+            request::headers(contains(("authorization", "Bearer abc123"))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_digest_auth() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/foo?a=1"
+
+    [auth]
+    type = "digest"
+    username = "Mufasa"
+    password = "Circle Of Life"
+    "#,
+        None,
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/foo"),
+            request::headers(not(contains(key("authorization")))),
+        ])
+        .respond_with(status_code(401).insert_header(
+            "www-authenticate",
+            r#"Digest realm="test", qop="auth", nonce="abc", opaque="xyz""#,
+        )),
+    );
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/foo"),
+            request::headers(contains((
+                "authorization",
+                matches(r#"^Digest username="Mufasa", realm="test", nonce="abc", uri="/foo\?a=1", algorithm=MD5, response="[0-9a-f]{32}", qop=auth, nc=00000001, cnonce="[0-9a-f]+", opaque="xyz"$"#)
+            ))),
+        ])
+        .respond_with(status_code(200).body("authenticated")),
+    );
+
+    run(fixture)
+        .success()
+        .stdout(predicate::str::contains("authenticated"));
+    Ok(())
+}
+
+#[test]
+fn test_aws_sigv4_auth() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/prod/items?b=2&a=1"
+
+    [body]
+    type = "json"
+    content = '''
+    {
+      "name": "widget"
+    }
+    '''
+
+    [auth]
+    type = "aws_sigv4"
+    region = "us-east-1"
+    service = "execute-api"
+    access_key = "{access_key}"
+    secret_key = "{secret_key}"
+    session_token = "session"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "access_key", value = "AKIDEXAMPLE" },
+          { name = "secret_key", value = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY" }
+        ]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/prod/items"),
+            request::headers(contains(("x-amz-date", matches(r"^\d{8}T\d{6}Z$")))),
+            request::headers(contains(("x-amz-security-token", "session"))),
+            request::headers(contains((
+                "authorization",
+                matches(r"^AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/\d{8}/us-east-1/execute-api/aws4_request, SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, Signature=[0-9a-f]{64}$")
+            ))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+#[test]
+fn test_hmac_auth() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/orders"
+
+    [body]
+    type = "text"
+    content = "{var1}"
+
+    [auth]
+    type = "hmac"
+    key = "{hmac_key}"
+    canonical = ["method", "path", "body"]
+    separator = "|"
+    header = "X-Signature"
+    encoding = "base64"
+    timestamp_header = "X-Timestamp"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "var1", value = "hello" },
+          { name = "hmac_key", value = "key" }
+        ]
+    "#,
+        ),
+    )?;
+
+    // HMAC-SHA256 of "POST|/orders|hello" with the key "key". The signature covers the body after
+    // its variables are bound.
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/orders"),
+            request::headers(contains(("x-timestamp", matches(r"^\d+$")))),
+            request::headers(contains((
+                "x-signature",
+                "Hx0HoN9v9u5qJpIp/SbuwKj/Ez7Rmsp4EjwbDwjKtjQ="
+            ))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    run(fixture).success();
+    Ok(())
+}
+
+/// Write a config file that keeps all of rhc's state files inside `dir`
+fn write_config(dir: &std::path::Path) -> anyhow::Result<std::path::PathBuf> {
+    let config_path = dir.join("config.toml");
+    std::fs::write(
+        &config_path,
+        format!(
+            r#"
+            request_definition_directory = "{dir}/definitions"
+            environment_directory = "{dir}/environments"
+            history_file = "{dir}/history"
+            session_file = "{dir}/session"
+            token_cache_file = "{dir}/tokens"
+            "#,
+            dir = dir.to_string_lossy()
+        ),
+    )?;
+
+    Ok(config_path)
+}
+
+#[test]
+fn test_extract_and_bind() -> anyhow::Result<()> {
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+
+    let login = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/login"
+
+    [extract]
+    values = [
+      { name = "token", type = "json", path = "$.data.token" },
+      { name = "session_id", type = "header", header = "x-session-id" },
+    ]
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = []
+    "#,
+        ),
+    )?;
+
+    login.server.expect(
+        Expectation::matching(request::method_path("POST", "/login")).respond_with(
+            status_code(200)
+                .insert_header("x-session-id", "abc123")
+                .body(r#"{"data": {"token": "secret-token"}}"#),
+        ),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("--environment")
+        .arg(login.env_file.as_ref().unwrap().path());
+    cmd.arg(login.def_file.path());
+    cmd.assert().success();
+
+    let fetch = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/items/{session_id}"
+
+    [headers]
+    headers = [
+      { name = "Authorization", value = "Bearer {token}" },
+    ]
+    "#,
+        None,
+    )?;
+
+    fetch.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/items/abc123"),
+            request::headers(contains(("authorization", "Bearer secret-token"))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    // Extracted values are saved per environment, so the same environment has to be used
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("--environment")
+        .arg(login.env_file.as_ref().unwrap().path());
+    cmd.arg(fetch.def_file.path());
+    cmd.assert().success();
+
+    Ok(())
+}
+
+#[test]
+fn test_secret_values_redacted() -> anyhow::Result<()> {
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+    let mut config = std::fs::read_to_string(&config_path)?;
+    config.push_str("secret_patterns = [\"(?i)token\"]\n");
+    std::fs::write(&config_path, config)?;
+
+    let fixture = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/login"
+
+    [extract]
+    values = [
+      { name = "access_token", type = "json", path = "$.token" },
+      { name = "session_id", type = "json", path = "$.session" },
+      { name = "user", type = "json", path = "$.user" },
+    ]
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = []
+        secrets = ["session_id"]
+    "#,
+        ),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(request::method_path("POST", "/login"))
+            .respond_with(status_code(200).body(
+                r#"{"token": "secret-token", "session": "secret-session", "user": "alice"}"#,
+            )),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("--verbose");
+    cmd.arg("--environment")
+        .arg(fixture.env_file.as_ref().unwrap().path());
+    cmd.arg(fixture.def_file.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Extracted user = alice"))
+        .stdout(predicate::str::contains(
+            "Extracted access_token = ********",
+        ))
+        .stdout(predicate::str::contains("Extracted session_id = ********"))
+        .stdout(predicate::str::contains("= secret-").not());
+
+    Ok(())
+}
+
+#[test]
+fn test_layered_environments() -> anyhow::Result<()> {
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+    let env_dir = state_dir.path().join("environments");
+    std::fs::create_dir(&env_dir)?;
+    std::fs::write(
+        env_dir.join("base.toml"),
+        r#"
+        name = "base"
+        variables = [
+          { name = "a", value = "base" },
+          { name = "b", value = "base" },
+          { name = "c", value = "base" },
+        ]
+        "#,
+    )?;
+    std::fs::write(
+        env_dir.join("staging.toml"),
+        r#"
+        name = "staging"
+        extends = "base.toml"
+        variables = [{ name = "b", value = "staging" }]
+        "#,
+    )?;
+    let override_file = state_dir.path().join("override.toml");
+    std::fs::write(
+        &override_file,
+        r#"
+        name = "override"
+        variables = [{ name = "c", value = "override" }]
+        "#,
+    )?;
+
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/{a}/{b}/{c}"
+    "#,
+        None,
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(request::method_path("GET", "/base/staging/override"))
+            .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("-e").arg(env_dir.join("staging.toml"));
+    cmd.arg("-e").arg(&override_file);
+    cmd.arg(fixture.def_file.path());
+    cmd.assert().success();
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypted_values() -> anyhow::Result<()> {
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+    let key_path = state_dir.path().join("key");
+    std::fs::write(&key_path, "first passphrase\n")?;
+    let mut config = std::fs::read_to_string(&config_path)?;
+    config.push_str(&format!("key_file = \"{}\"\n", key_path.display()));
+    std::fs::write(&config_path, config)?;
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("encrypt");
+    cmd.write_stdin("secret-token\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let encrypted = String::from_utf8(output)?.trim().to_string();
+    assert!(encrypted.starts_with("enc:v1:"));
+
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/items"
+
+    [headers]
+    headers = [
+      { name = "Authorization", value = "Bearer {token}" },
+    ]
+    "#,
+        Some(&format!(
+            r#"
+        name = "test_env"
+        variables = [
+          {{ name = "token", value = "{}" }},
+        ]
+    "#,
+            encrypted
+        )),
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/items"),
+            request::headers(contains(("authorization", "Bearer secret-token"))),
+        ])
+        .times(2)
+        .respond_with(status_code(200)),
+    );
+
+    let env_path = fixture.env_file.as_ref().unwrap().path();
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("--environment").arg(env_path);
+    cmd.arg(fixture.def_file.path());
+    cmd.assert().success();
+
+    // Re-encrypt the environment file with a new key file, which the old one can't decrypt
+    let new_key_path = state_dir.path().join("new_key");
+    std::fs::write(&new_key_path, "second passphrase")?;
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("rekey").arg(env_path).arg(&new_key_path);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Re-encrypted 1 values"));
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("--environment").arg(env_path);
+    cmd.arg(fixture.def_file.path());
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Could not decrypt the value of token",
+    ));
+
+    std::fs::rename(&new_key_path, &key_path)?;
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("--environment").arg(env_path);
+    cmd.arg(fixture.def_file.path());
+    cmd.assert().success();
+
+    Ok(())
+}
+
+#[test]
+fn test_extract_missing_value() -> anyhow::Result<()> {
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/foo"
+
+    [extract]
+    values = [
+      { name = "token", type = "json", path = "data.token" },
+    ]
+    "#,
+        None,
+    )?;
+
+    fixture.server.expect(
+        Expectation::matching(request::method_path("GET", "/foo"))
+            .respond_with(status_code(200).body(r#"{"data": {}}"#)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg(fixture.def_file.path());
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Nothing found at JSON path data.token",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_test_command() -> anyhow::Result<()> {
+    let server = Server::run();
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("nested"))?;
+
+    std::fs::write(
+        dir.path().join("passing.toml"),
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/{path}"
+
+    [assertions]
+    status = "2xx"
+    headers = [{ name = "content-type", contains = "json" }]
+    json = [{ path = "$.items[0].id", equals = 1 }]
+    "#
+        .replace("__base_url__/", &server.url_str("")),
+    )?;
+    std::fs::write(
+        dir.path().join("nested").join("failing.toml"),
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/{path}"
+
+    [assertions]
+    status = 201
+    body_matches = "created"
+    "#
+        .replace("__base_url__/", &server.url_str("")),
+    )?;
+
+    // Workflows kept alongside the definitions aren't tests themselves
+    std::fs::write(
+        dir.path().join("flow.toml"),
+        r#"
+    [[steps]]
+    definition = "passing.toml"
+    "#,
+    )?;
+
+    let mut env_file = NamedTempFile::new()?;
+    write!(
+        env_file,
+        r#"
+        name = "test_env"
+        variables = [{{ name = "path", value = "items" }}]
+        "#
+    )?;
+
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/items")).respond_with(
+            status_code(200)
+                .insert_header("content-type", "application/json")
+                .body(r#"{"items": [{"id": 1}]}"#),
+        ),
+    );
+    server.expect(
+        Expectation::matching(request::method_path("POST", "/items"))
+            .respond_with(status_code(200).body("nope")),
+    );
+
+    // The environment is passed after the subcommand, to check that it's accepted there too
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("test").arg(dir.path());
+    cmd.arg("--environment").arg(env_file.path());
+
+    let report_dir = tempfile::tempdir()?;
+    let junit_path = report_dir.path().join("junit.xml");
+    let json_path = report_dir.path().join("report.json");
+    cmd.arg("--junit-report").arg(&junit_path);
+    cmd.arg("--json-report").arg(&json_path);
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("PASS  passing.toml"))
+        .stdout(predicate::str::contains("FAIL  nested/failing.toml"))
+        .stdout(predicate::str::contains("Expected status 201, got 200"))
+        .stdout(predicate::str::contains(
+            "Expected the body to match created",
+        ))
+        .stdout(predicate::str::contains("1 passed, 1 failed"))
+        .stderr(predicate::str::contains("1 of 2 tests failed"));
+
+    let junit = std::fs::read_to_string(&junit_path)?;
+    assert!(junit.contains(r#"<testsuite name="rhc" tests="2" failures="1" errors="0""#));
+    assert!(junit.contains(r#"<testcase name="nested/failing.toml" classname="nested""#));
+    assert!(junit.contains(r#"<failure message="Expected status 201, got 200">"#));
+
+    let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&json_path)?)?;
+    assert_eq!(json["tests"], 2);
+    assert_eq!(json["failed"], 1);
+    assert_eq!(json["results"][0]["name"], "nested/failing.toml");
+    assert_eq!(json["results"][0]["request"]["method"], "POST");
+    assert_eq!(
+        json["results"][0]["request"]["url"],
+        server.url_str("/items")
+    );
+    assert_eq!(json["results"][0]["response"]["status"], 200);
+    assert_eq!(json["results"][1]["passed"], true);
+
+    Ok(())
+}
+
+#[test]
+fn test_test_command_unbound() -> anyhow::Result<()> {
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/{unbound}"
+    "#,
+        None,
+    )?;
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("test").arg(fixture.def_file.path());
+
+    // Tests never prompt for variables
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("Unbound variables: unbound"));
+
+    Ok(())
+}
+
+#[test]
+fn test_workflow() -> anyhow::Result<()> {
+    let server = Server::run();
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+
+    let def_dir = state_dir.path().join("definitions");
+    std::fs::create_dir_all(def_dir.join("users"))?;
+    std::fs::write(
+        def_dir.join("users").join("create.toml"),
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/users"
+
+    [extract]
+    values = [{ name = "user_id", type = "json", path = "id" }]
+    "#
+        .replace("__base_url__/", &server.url_str("")),
+    )?;
+    std::fs::write(
+        def_dir.join("users").join("get.toml"),
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/users/{user_id}"
+
+    [headers]
+    headers = [{ name = "x-role", value = "{role}" }]
+
+    [assertions]
+    status = 200
+    "#
+        .replace("__base_url__/", &server.url_str("")),
+    )?;
+    std::fs::write(
+        def_dir.join("users").join("delete.toml"),
+        r#"
+    [request]
+    method = "DELETE"
+    url = "__base_url__/users/{user_id}"
+    "#
+        .replace("__base_url__/", &server.url_str("")),
+    )?;
+
+    let mut workflow_file = NamedTempFile::new()?;
+    write!(
+        workflow_file,
+        r#"
+        name = "User lifecycle"
+
+        [[steps]]
+        definition = "users/create.toml"
+
+        [[steps]]
+        definition = "users/get.toml"
+        bindings = [{{ name = "role", value = "admin" }}]
+
+        [[steps]]
+        definition = "users/delete.toml"
+        "#
+    )?;
+
+    server.expect(
+        Expectation::matching(request::method_path("POST", "/users"))
+            .respond_with(status_code(201).body(r#"{"id": 42}"#)),
+    );
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/users/42"),
+            request::headers(contains(("x-role", "admin"))),
+        ])
+        .respond_with(status_code(200)),
+    );
+    server.expect(
+        Expectation::matching(request::method_path("DELETE", "/users/42"))
+            .respond_with(status_code(204)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("workflow").arg(workflow_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("User lifecycle"))
+        .stdout(predicate::str::contains("PASS  users/create.toml"))
+        .stdout(predicate::str::contains("PASS  users/get.toml"))
+        .stdout(predicate::str::contains("PASS  users/delete.toml"))
+        .stdout(predicate::str::contains("3 passed, 0 failed, 0 skipped"));
+
+    Ok(())
+}
+
+#[test]
+fn test_workflow_stop_on_failure() -> anyhow::Result<()> {
+    let server = Server::run();
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+
+    let def_dir = state_dir.path().join("definitions");
+    std::fs::create_dir_all(&def_dir)?;
+    std::fs::write(
+        def_dir.join("first.toml"),
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/first"
+
+    [assertions]
+    status = 200
+    "#
+        .replace("__base_url__/", &server.url_str("")),
+    )?;
+    std::fs::write(
+        def_dir.join("second.toml"),
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/second"
+    "#
+        .replace("__base_url__/", &server.url_str("")),
+    )?;
+
+    let mut workflow_file = NamedTempFile::new()?;
+    write!(
+        workflow_file,
+        r#"
+        [[steps]]
+        definition = "first.toml"
+
+        [[steps]]
+        definition = "second.toml"
+        "#
+    )?;
+
+    // The second step must never be sent
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/first")).respond_with(status_code(500)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg("workflow").arg(workflow_file.path());
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("FAIL  first.toml"))
+        .stdout(predicate::str::contains("0 passed, 1 failed, 1 skipped"))
+        .stderr(predicate::str::contains("1 of 2 steps failed"));
+
+    Ok(())
+}
+
+#[test]
+fn test_cookie_jar() -> anyhow::Result<()> {
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+    let mut config = std::fs::read_to_string(&config_path)?;
+    config.push_str(&format!(
+        "cookie_jar = \"{}/cookies\"\n",
+        state_dir.path().to_string_lossy()
+    ));
+    std::fs::write(&config_path, config)?;
+
+    let login = setup(
+        r#"
+    [request]
+    method = "POST"
+    url = "__base_url__/login"
+    "#,
+        None,
+    )?;
+
+    login.server.expect(
+        Expectation::matching(request::method_path("POST", "/login")).respond_with(
+            status_code(200)
+                .append_header("set-cookie", "sid=abc123; Path=/; HttpOnly")
+                .append_header("set-cookie", "other=1; Path=/elsewhere"),
+        ),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg(login.def_file.path());
+    cmd.assert().success();
+
+    let fetch = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/me"
+    "#,
+        None,
+    )?;
+
+    fetch.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/me"),
+            request::headers(contains(("cookie", "sid=abc123"))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("--config").arg(&config_path);
+    cmd.arg(fetch.def_file.path());
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("cookies")
+        .arg("list")
+        .arg("--config")
+        .arg(&config_path);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("sid=abc123"))
+        .stdout(predicate::str::contains("other=1"));
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("cookies")
+        .arg("clear")
+        .arg("--config")
+        .arg(&config_path);
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("main").unwrap();
+    cmd.arg("cookies")
+        .arg("list")
+        .arg("--config")
+        .arg(&config_path);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("sid=abc123").not());
+
+    Ok(())
+}
+
+#[test]
+fn test_oauth2_client_credentials() -> anyhow::Result<()> {
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+
+    // The auth table can also go in the environment file
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/items"
+    "#,
+        Some(
+            r#"
+        name = "test_env"
+        variables = [
+          { name = "secret", value = "s3cret" }
+        ]
+
+        [auth]
+        type = "oauth2"
+        token_url = "__base_url__/token"
+        client_id = "rhc"
+        client_secret = "{secret}"
+        scopes = ["read", "write"]
+    "#,
+        ),
+    )?;
+    let env_file = fixture.env_file.as_ref().unwrap();
+    let env = std::fs::read_to_string(env_file.path())?
+        .replace("__base_url__/", &fixture.server.url_str(""));
+    std::fs::write(env_file.path(), env)?;
+
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/token"),
+            request::headers(contains(("authorization", "Basic cmhjOnMzY3JldA=="))),
+            request::body(url_decoded(contains(("grant_type", "client_credentials")))),
+            request::body(url_decoded(contains(("scope", "read write")))),
+        ])
+        .times(1)
+        .respond_with(json_encoded(
+            serde_json::json!({ "access_token": "token1", "expires_in": 3600 }),
+        )),
+    );
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/items"),
+            request::headers(contains(("authorization", "Bearer token1"))),
+        ])
+        .times(2)
+        .respond_with(status_code(200)),
+    );
+
+    // The second run uses the cached token
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin("main").unwrap();
+        cmd.arg("--config").arg(&config_path);
+        cmd.arg("--environment").arg(env_file.path());
+        cmd.arg(fixture.def_file.path());
+        cmd.assert().success();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_oauth2_refresh() -> anyhow::Result<()> {
+    let state_dir = tempfile::tempdir()?;
+    let config_path = write_config(state_dir.path())?;
+
+    let fixture = setup(
+        r#"
+    [request]
+    method = "GET"
+    url = "__base_url__/items"
+
+    [auth]
+    type = "oauth2"
+    token_url = "__base_url__/token"
+    client_id = "rhc"
+    "#,
+        None,
+    )?;
+
+    // The first token has already expired by the time the second request is sent
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/token"),
+            request::body(url_decoded(contains(("grant_type", "client_credentials")))),
+            request::body(url_decoded(contains(("client_id", "rhc")))),
+        ])
+        .times(1)
+        .respond_with(json_encoded(serde_json::json!({
+            "access_token": "token1",
+            "expires_in": 0,
+            "refresh_token": "refresh1",
+        }))),
+    );
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/token"),
+            request::body(url_decoded(contains(("grant_type", "refresh_token")))),
+            request::body(url_decoded(contains(("refresh_token", "refresh1")))),
+        ])
+        .times(1)
+        .respond_with(json_encoded(
+            serde_json::json!({ "access_token": "token2", "expires_in": 3600 }),
+        )),
+    );
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/items"),
+            request::headers(contains(("authorization", "Bearer token1"))),
+        ])
+        .times(1)
+        .respond_with(status_code(200)),
+    );
+    fixture.server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/items"),
+            request::headers(contains(("authorization", "Bearer token2"))),
+        ])
+        .times(1)
+        .respond_with(status_code(200)),
+    );
+
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin("main").unwrap();
+        cmd.arg("--config").arg(&config_path);
+        cmd.arg(fixture.def_file.path());
+        cmd.assert().success();
+    }
+
     Ok(())
 }